@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Fewest runs per side from which the confidence interval is considered.
+const MIN_RUNS: usize = 3;
+
+/// Most runs per side by default.
+pub const DEFAULT_MAX_RUNS: usize = 100;
+
+/// Two-sided 95% quantiles of Student's t-distribution for 1 to 30 degrees of freedom.
+const T_QUANTILES: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Two-sided 95% quantile of the normal distribution, for large degrees of freedom.
+const Z_QUANTILE: f64 = 1.96;
+
+/// Settings of the adaptive run count, which keeps measuring base and head
+/// until the difference between them is known precisely enough.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adaptive {
+    /// Width in percentage points of the 95% confidence interval of the relative
+    /// wall time difference, below which measuring stops.
+    pub target_width: f64,
+    /// Most runs per side.
+    pub max_runs: usize,
+    /// Time after which measuring a benchmark stops, if any.
+    pub time_budget: Option<Duration>,
+}
+
+/// How adaptive measuring of a benchmark ended.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Outcome {
+    /// Number of runs per side.
+    pub runs: usize,
+    /// 95% confidence interval of the relative wall time difference in percent, if known.
+    pub interval: Option<(f64, f64)>,
+    /// Whether the interval got narrower than the target width.
+    pub reached: bool,
+}
+
+impl Adaptive {
+    /// Whether to stop measuring after the given runs per side,
+    /// with wall times in seconds.
+    /// Measures at least `min_runs` runs per side, and no fewer than three.
+    #[must_use]
+    pub fn is_done(&self, min_runs: usize, base: &[f64], head: &[f64], elapsed: Duration) -> bool {
+        let runs = base.len().min(head.len());
+        runs >= self.max_runs
+            || self.time_budget.is_some_and(|budget| elapsed >= budget)
+            || (runs >= min_runs.max(MIN_RUNS) && self.is_precise(base, head))
+    }
+
+    /// Summarize how measuring ended, with wall times in seconds.
+    #[must_use]
+    pub fn outcome(&self, base: &[f64], head: &[f64]) -> Outcome {
+        Outcome {
+            runs: base.len().min(head.len()),
+            interval: confidence_interval(base, head),
+            reached: self.is_precise(base, head),
+        }
+    }
+
+    /// Whether the confidence interval is narrower than the target width.
+    fn is_precise(&self, base: &[f64], head: &[f64]) -> bool {
+        confidence_interval(base, head).is_some_and(|(low, high)| high - low < self.target_width)
+    }
+}
+
+/// Mean and sample variance of values.
+#[allow(clippy::cast_precision_loss)]
+fn mean_and_variance(values: &[f64]) -> (f64, f64) {
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (count - 1.0);
+    (mean, variance)
+}
+
+/// 95% confidence interval of the relative difference of the head mean
+/// to the base mean, in percent.
+///
+/// Uses the delta method for the ratio of means and Welch's approximation
+/// of the degrees of freedom. Requires at least two positive values per side.
+#[must_use]
+pub fn confidence_interval(base: &[f64], head: &[f64]) -> Option<(f64, f64)> {
+    if base.len() < 2 || head.len() < 2 {
+        return None;
+    }
+    let (base_mean, base_variance) = mean_and_variance(base);
+    let (head_mean, head_variance) = mean_and_variance(head);
+    if base_mean <= 0.0 || head_mean <= 0.0 {
+        return None;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let (base_count, head_count) = (base.len() as f64, head.len() as f64);
+    // Squared relative standard errors of both means.
+    let base_error = base_variance / (base_count * base_mean.powi(2));
+    let head_error = head_variance / (head_count * head_mean.powi(2));
+    let ratio = head_mean / base_mean;
+    let standard_error = ratio * (base_error + head_error).sqrt();
+    let degrees_of_freedom = (base_error + head_error).powi(2)
+        / (base_error.powi(2) / (base_count - 1.0) + head_error.powi(2) / (head_count - 1.0));
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let quantile = if degrees_of_freedom.is_finite() && degrees_of_freedom >= 1.0 {
+        T_QUANTILES
+            .get(degrees_of_freedom as usize - 1)
+            .copied()
+            .unwrap_or(Z_QUANTILE)
+    } else {
+        // Without any variance, the interval is empty anyway.
+        Z_QUANTILE
+    };
+    let margin = quantile * standard_error;
+    Some((
+        (ratio - 1.0 - margin) * 100.0,
+        (ratio - 1.0 + margin) * 100.0,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{confidence_interval, Adaptive};
+    use std::time::Duration;
+
+    const ADAPTIVE: Adaptive = Adaptive {
+        target_width: 5.0,
+        max_runs: 10,
+        time_budget: Some(Duration::from_mins(1)),
+    };
+
+    #[test]
+    fn interval() {
+        assert_eq!(confidence_interval(&[1.0], &[1.0, 2.0]), None);
+        let (low, high) = confidence_interval(&[1.0, 1.0, 1.0], &[1.1, 1.1, 1.1]).unwrap();
+        assert!((low - 10.0).abs() < 1e-9 && (high - 10.0).abs() < 1e-9);
+        let (low, high) = confidence_interval(&[0.9, 1.0, 1.1], &[1.9, 2.0, 2.1]).unwrap();
+        assert!(low < 100.0 && high > 100.0);
+    }
+
+    #[test]
+    fn stopping() {
+        let stable = [1.0, 1.01, 0.99];
+        let noisy = [1.0, 1.5, 0.5];
+        assert!(!ADAPTIVE.is_done(1, &stable[..2], &stable[..2], Duration::ZERO));
+        assert!(ADAPTIVE.is_done(1, &stable, &stable, Duration::ZERO));
+        assert!(!ADAPTIVE.is_done(4, &stable, &stable, Duration::ZERO));
+        assert!(!ADAPTIVE.is_done(1, &noisy, &noisy, Duration::ZERO));
+        assert!(ADAPTIVE.is_done(1, &noisy, &noisy, Duration::from_mins(1)));
+        // Stops at the maximum run count, even if still noisy.
+        let many = [1.0, 1.5].repeat(5);
+        assert!(ADAPTIVE.is_done(1, &many, &many, Duration::ZERO));
+        let outcome = ADAPTIVE.outcome(&stable, &stable);
+        assert_eq!(outcome.runs, 3);
+        assert!(outcome.reached);
+    }
+}
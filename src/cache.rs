@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+
+use crate::measurement::Summary;
+
+/// Cached results of measuring all benchmarks on one tree.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Settings the results were measured with, compared in full to rule out hash collisions.
+    fingerprint: String,
+    /// Results of each benchmark, in the configured order.
+    summaries: Vec<Summary>,
+}
+
+/// Results of previous measurements on disk, keyed on the tree of the measured commit
+/// and the settings affecting the measurements.
+///
+/// Commits with identical trees, e.g. after a rebase, build and run the same files,
+/// so their results are interchangeable.
+pub struct Cache {
+    /// Directory containing one JSON file per entry.
+    dir: PathBuf,
+    /// Settings affecting the measurements.
+    fingerprint: String,
+}
+
+impl Cache {
+    /// Cache in the given directory, for measurements with the given settings.
+    #[must_use]
+    pub const fn new(dir: PathBuf, fingerprint: String) -> Self {
+        Self { dir, fingerprint }
+    }
+
+    /// Results measured on a tree with the same settings, if any.
+    /// Unreadable entries are treated as missing.
+    #[must_use]
+    pub fn load(&self, tree: git2::Oid) -> Option<Vec<Summary>> {
+        let json = std::fs::read_to_string(self.path(tree)).ok()?;
+        let entry: Entry = serde_json::from_str(&json).ok()?;
+        (entry.fingerprint == self.fingerprint).then_some(entry.summaries)
+    }
+
+    /// Save the results measured on a tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written.
+    pub fn store(&self, tree: git2::Oid, summaries: &[Summary]) -> Result<()> {
+        let path = self.path(tree);
+        let entry = Entry {
+            fingerprint: self.fingerprint.clone(),
+            summaries: summaries.to_vec(),
+        };
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        std::fs::write(&path, serde_json::to_string(&entry)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// File of the entry for a tree.
+    fn path(&self, tree: git2::Oid) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        self.fingerprint.hash(&mut hasher);
+        self.dir
+            .join(format!("{tree}-{:016x}.json", hasher.finish()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use crate::measurement::Summary;
+
+    #[test]
+    fn keyed_on_tree_and_settings() {
+        let dir = std::env::temp_dir().join(format!("git-perfdiff-cache-{}", std::process::id()));
+        let tree = git2::Oid::from_str("4b825dc642cb6eb9a060e54bf8d69288fbee4904").unwrap();
+        let other_tree = git2::Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let cache = Cache::new(dir.clone(), "runs: 3".to_string());
+        assert!(cache.load(tree).is_none());
+
+        cache.store(tree, &[Summary::default()]).unwrap();
+        assert_eq!(cache.load(tree).map(|summaries| summaries.len()), Some(1));
+        assert!(cache.load(other_tree).is_none());
+        assert!(Cache::new(dir.clone(), "runs: 5".to_string())
+            .load(tree)
+            .is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
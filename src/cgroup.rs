@@ -0,0 +1,454 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::Duration;
+
+/// Controllers providing the statistics and limits, enabled where available.
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "io"];
+
+/// Period of the CPU limit in microseconds, as used by the kernel by default.
+const CPU_PERIOD: u32 = 100_000;
+
+/// How often removing a cgroup is attempted while its killed processes exit.
+const REMOVE_ATTEMPTS: usize = 100;
+
+/// Number of cgroups created so far, used to name them uniquely.
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// CPU time of the process tree in seconds.
+pub const CPU_TIME: &str = "cpu_time";
+
+/// User CPU time of the process tree in seconds.
+pub const USER_TIME: &str = "user_time";
+
+/// System CPU time of the process tree in seconds.
+pub const SYSTEM_TIME: &str = "system_time";
+
+/// Time the process tree was throttled by the CPU limit, in seconds.
+pub const THROTTLED_TIME: &str = "throttled_time";
+
+/// Peak memory usage of the process tree in bytes.
+pub const MEMORY_PEAK: &str = "memory_peak";
+
+/// Bytes read from block devices.
+pub const IO_READ_BYTES: &str = "io_read_bytes";
+
+/// Bytes written to block devices.
+pub const IO_WRITE_BYTES: &str = "io_write_bytes";
+
+/// Number of read operations on block devices.
+pub const IO_READS: &str = "io_reads";
+
+/// Number of write operations on block devices.
+pub const IO_WRITES: &str = "io_writes";
+
+/// Names of all cgroup statistics that may be reported.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    [
+        CPU_TIME,
+        USER_TIME,
+        SYSTEM_TIME,
+        THROTTLED_TIME,
+        MEMORY_PEAK,
+        IO_READ_BYTES,
+        IO_WRITE_BYTES,
+        IO_READS,
+        IO_WRITES,
+    ]
+    .into_iter()
+}
+
+/// Resource limits enforced on a measured process tree.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// Maximum memory usage, in bytes or with a `K`, `M` or `G` suffix.
+    /// The process tree is killed by the OOM killer when exceeding it.
+    pub memory: Option<String>,
+    /// Maximum CPU bandwidth in CPUs, e.g. `1.5` for one and a half CPUs.
+    pub cpus: Option<f64>,
+}
+
+impl Limits {
+    /// Check that the limits are within the ranges accepted by the kernel.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        let valid_memory = |memory: &String| {
+            let digits = memory.trim_end_matches(['K', 'M', 'G', 'k', 'm', 'g']);
+            !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+        };
+        self.memory.as_ref().is_none_or(valid_memory)
+            && self
+                .cpus
+                .is_none_or(|cpus| cpus.is_finite() && cpus >= 0.01)
+    }
+}
+
+/// Whether cgroup v2 is available and the current process' cgroup can be read.
+#[must_use]
+pub fn is_available() -> bool {
+    own_cgroup().is_ok_and(|path| path.join("cgroup.procs").is_file())
+}
+
+/// Directory of the cgroup of the current process in the unified hierarchy.
+fn own_cgroup() -> io::Result<PathBuf> {
+    let not_found = |what: &str| io::Error::new(io::ErrorKind::NotFound, what);
+    let mounts = std::fs::read_to_string("/proc/self/mountinfo")?;
+    // The mount point is the fifth field, the filesystem type follows the separator.
+    let mount_point = mounts
+        .lines()
+        .find_map(|line| {
+            let (fields, filesystem) = line.split_once(" - ")?;
+            filesystem
+                .starts_with("cgroup2 ")
+                .then(|| fields.split(' ').nth(4))?
+        })
+        .ok_or_else(|| not_found("no cgroup v2 hierarchy is mounted"))?;
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup")?;
+    let path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| not_found("the process is not in a cgroup v2 hierarchy"))?;
+    Ok(Path::new(mount_point).join(path.trim_start_matches('/')))
+}
+
+/// Name of the leaf cgroup holding this process within its subtree.
+const LEAF: &str = "self";
+
+/// Subtree of the cgroup this process was started in, while anything holds it.
+static SUBTREE: Mutex<Weak<Subtree>> = Mutex::new(Weak::new());
+
+/// Directories of the subtree of the process with the given PID in the cgroup `origin`,
+/// and of the leaf holding the process within it.
+fn subtree_dirs(origin: &Path, pid: u32) -> (PathBuf, PathBuf) {
+    let subtree = origin.join(format!("git-perfdiff-{pid}"));
+    let leaf = subtree.join(LEAF);
+    (subtree, leaf)
+}
+
+/// Directory of a cgroup of a measured process tree, a sibling of the leaf.
+fn run_dir(subtree: &Path, index: usize) -> PathBuf {
+    subtree.join(format!("run-{index}"))
+}
+
+/// A child of the cgroup this process was started in, containing this process
+/// in a leaf and the measured process trees in sibling cgroups.
+///
+/// Controllers can only be enabled for the children of cgroups without processes,
+/// except for the root cgroup. Moving this process out of the way allows enabling them,
+/// if no other processes are in the cgroup it was started in.
+/// Undone on drop, moving this process back.
+///
+/// Meant to be held for a whole session, so that this process is not moved
+/// and controllers are not toggled between measured runs.
+pub struct Subtree {
+    /// Cgroup this process was started in.
+    origin: PathBuf,
+    /// Directory of the subtree.
+    path: PathBuf,
+    /// Controllers enabled by this process in `origin` and in the subtree.
+    enabled: (Vec<&'static str>, Vec<&'static str>),
+}
+
+impl Subtree {
+    /// The subtree of this process, created if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the subtree cannot be created or this process cannot move into it.
+    pub fn shared() -> Result<Arc<Self>> {
+        let mut shared = SUBTREE.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(subtree) = shared.upgrade() {
+            return Ok(subtree);
+        }
+        let subtree = Arc::new(Self::create()?);
+        *shared = Arc::downgrade(&subtree);
+        drop(shared);
+        Ok(subtree)
+    }
+
+    /// Create the subtree, move this process into its leaf and enable the controllers.
+    fn create() -> Result<Self> {
+        let origin = own_cgroup().context("cgroup v2 is unavailable")?;
+        let (path, leaf) = subtree_dirs(&origin, std::process::id());
+        std::fs::create_dir_all(&leaf)
+            .with_context(|| format!("Failed to create cgroup {}", leaf.display()))?;
+        let mut subtree = Self {
+            origin,
+            path,
+            enabled: (Vec::new(), Vec::new()),
+        };
+        std::fs::write(leaf.join("cgroup.procs"), std::process::id().to_string())
+            .with_context(|| format!("Failed to move into cgroup {}", leaf.display()))?;
+        subtree.enabled = (
+            enable_controllers(&subtree.origin),
+            enable_controllers(&subtree.path),
+        );
+        Ok(subtree)
+    }
+}
+
+impl Drop for Subtree {
+    fn drop(&mut self) {
+        // Processes can only move into cgroups without enabled controllers, except for the root.
+        let (in_origin, in_subtree) = &self.enabled;
+        disable_controllers(&self.path, in_subtree);
+        disable_controllers(&self.origin, in_origin);
+        let moved = std::fs::write(
+            self.origin.join("cgroup.procs"),
+            std::process::id().to_string(),
+        );
+        if moved.is_err()
+            || std::fs::remove_dir(self.path.join(LEAF)).is_err()
+            || std::fs::remove_dir(&self.path).is_err()
+        {
+            eprintln!(
+                "Warning: Failed to remove cgroup {}, please remove it manually.",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// A transient cgroup v2 containing a measured process tree.
+///
+/// Created in the [`Subtree`] of the cgroup of the current process, which therefore
+/// must be writable, e.g. when running as root or in a delegated subtree.
+/// Statistics and limits are only available if that cgroup contains no other processes,
+/// or is the root cgroup, e.g. in a scope started with
+/// `systemd-run --user --scope -p Delegate=yes git perfdiff ...`.
+/// Removed on drop, killing all processes left in it.
+pub struct Cgroup {
+    /// Directory of the cgroup.
+    path: PathBuf,
+    /// Subtree containing the cgroup, kept until all its cgroups are removed.
+    _subtree: Arc<Subtree>,
+}
+
+impl Cgroup {
+    /// Create an empty cgroup enforcing the given limits,
+    /// in the subtree held for the session, or in a new one if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cgroup cannot be created,
+    /// or a limit cannot be set because its controller is unavailable.
+    pub fn create(limits: &Limits) -> Result<Self> {
+        let subtree = Subtree::shared()?;
+        let path = run_dir(&subtree.path, CREATED.fetch_add(1, Ordering::Relaxed));
+        std::fs::create_dir(&path)
+            .with_context(|| format!("Failed to create cgroup {}", path.display()))?;
+        let cgroup = Self {
+            path,
+            _subtree: subtree,
+        };
+        if let Some(memory) = &limits.memory {
+            cgroup.write("memory.max", memory)?;
+        }
+        if let Some(cpus) = limits.cpus {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let quota = (cpus * f64::from(CPU_PERIOD)).round() as u64;
+            cgroup.write("cpu.max", &format!("{quota} {CPU_PERIOD}"))?;
+        }
+        Ok(cgroup)
+    }
+
+    /// Start the command in this cgroup, so that all its descendants are in it as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cgroup cannot be joined.
+    pub fn attach(&self, command: &mut std::process::Command) -> io::Result<()> {
+        let procs: File = OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))?;
+        // SAFETY: Writing to an open file descriptor is a plain system call,
+        // it neither allocates nor takes locks.
+        // The file is owned by the closure and thus stays open until the command is spawned.
+        unsafe {
+            command.pre_exec(move || {
+                // Writing `0` moves the writing process.
+                let written = libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1);
+                if written == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Statistics of all processes that ran in the cgroup, by name.
+    /// Statistics of unavailable controllers are missing.
+    #[must_use]
+    pub fn stats(&self) -> BTreeMap<String, f64> {
+        let read = |file: &str| std::fs::read_to_string(self.path.join(file)).unwrap_or_default();
+        let mut stats = BTreeMap::new();
+        let cpu_stat = read("cpu.stat");
+        let cpu_stat = parse_flat_keyed(&cpu_stat);
+        for (key, name) in [
+            ("usage_usec", CPU_TIME),
+            ("user_usec", USER_TIME),
+            ("system_usec", SYSTEM_TIME),
+            ("throttled_usec", THROTTLED_TIME),
+        ] {
+            if let Some(microseconds) = cpu_stat.get(key) {
+                stats.insert(name.to_string(), microseconds / 1e6);
+            }
+        }
+        if let Ok(peak) = read("memory.peak").trim().parse() {
+            stats.insert(MEMORY_PEAK.to_string(), peak);
+        }
+        // One line per device, e.g. `8:0 rbytes=1 wbytes=2 rios=3 wios=4 ...`.
+        // Devices without any I/O are not listed, so the file may be empty.
+        if self.path.join("io.stat").is_file() {
+            let io_stat = read("io.stat");
+            for (key, name) in [
+                ("rbytes", IO_READ_BYTES),
+                ("wbytes", IO_WRITE_BYTES),
+                ("rios", IO_READS),
+                ("wios", IO_WRITES),
+            ] {
+                let total = io_stat
+                    .split_whitespace()
+                    .filter_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+                    .filter_map(|value| value.parse::<f64>().ok())
+                    .sum();
+                stats.insert(name.to_string(), total);
+            }
+        }
+        stats
+    }
+
+    /// Write a value to a control file of the cgroup.
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        std::fs::write(self.path.join(file), value).map_err(|err| {
+            anyhow!(
+                "Failed to set `{file}` of cgroup {} to `{value}`, \
+                 the controller may be unavailable: {err}",
+                self.path.display()
+            )
+        })
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Kill processes left behind, e.g. daemons started by the benchmark.
+        if let Ok(mut kill) = OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.kill"))
+        {
+            let _ = kill.write_all(b"1");
+        }
+        // The cgroup can only be removed once its processes have exited.
+        for _ in 0..REMOVE_ATTEMPTS {
+            if std::fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        eprintln!(
+            "Warning: Failed to remove cgroup {}, please remove it manually.",
+            self.path.display()
+        );
+    }
+}
+
+/// Enable the controllers for the children of a cgroup, as far as possible,
+/// returning those that were not enabled before.
+///
+/// Fails for controllers the cgroup does not have, and in cgroups other than the root
+/// that contain processes themselves, in which case statistics and limits
+/// of those controllers are unavailable.
+fn enable_controllers(cgroup: &Path) -> Vec<&'static str> {
+    let read = |file: &str| std::fs::read_to_string(cgroup.join(file)).unwrap_or_default();
+    let (available, enabled) = (read("cgroup.controllers"), read("cgroup.subtree_control"));
+    let has = |list: &str, controller: &str| list.split_whitespace().any(|name| name == controller);
+    CONTROLLERS
+        .into_iter()
+        .filter(|controller| has(&available, controller) && !has(&enabled, controller))
+        .filter(|controller| {
+            std::fs::write(
+                cgroup.join("cgroup.subtree_control"),
+                format!("+{controller}"),
+            )
+            .is_ok()
+        })
+        .collect()
+}
+
+/// Disable controllers for the children of a cgroup, as far as possible.
+fn disable_controllers(cgroup: &Path, controllers: &[&str]) {
+    for controller in controllers {
+        let _ = std::fs::write(
+            cgroup.join("cgroup.subtree_control"),
+            format!("-{controller}"),
+        );
+    }
+}
+
+/// Parse a flat keyed file such as `cpu.stat`, with one `key value` pair per line.
+fn parse_flat_keyed(contents: &str) -> BTreeMap<&str, f64> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key, value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_flat_keyed, run_dir, subtree_dirs, Limits};
+    use std::path::Path;
+
+    #[test]
+    fn limits() {
+        assert!(Limits::default().is_valid());
+        let valid = Limits {
+            memory: Some("512M".to_string()),
+            cpus: Some(1.5),
+        };
+        assert!(valid.is_valid());
+        for invalid in [
+            Limits {
+                memory: Some("lots".to_string()),
+                ..Limits::default()
+            },
+            Limits {
+                memory: Some("M".to_string()),
+                ..Limits::default()
+            },
+            Limits {
+                cpus: Some(0.0),
+                ..Limits::default()
+            },
+        ] {
+            assert!(!invalid.is_valid());
+        }
+    }
+
+    #[test]
+    fn flat_keyed() {
+        let stat = parse_flat_keyed("usage_usec 1500\nuser_usec 1000\nbroken\n");
+        assert_eq!(stat.len(), 2);
+        assert!((stat["usage_usec"] - 1500.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn layout() {
+        let origin = Path::new("/sys/fs/cgroup/user.slice/session-1.scope");
+        let (subtree, leaf) = subtree_dirs(origin, 42);
+        assert_eq!(subtree, origin.join("git-perfdiff-42"));
+        // This process and the measured process trees are siblings in the subtree.
+        assert_eq!(leaf, subtree.join("self"));
+        assert_eq!(run_dir(&subtree, 3), subtree.join("run-3"));
+    }
+}
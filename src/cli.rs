@@ -1,6 +1,9 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+
+use crate::outliers::Method as OutlierMethod;
+use crate::schedule::Schedule;
 
 /// Measure performance of a program across git commits.
 // TODO: Remove Clone once everything is added to Config
@@ -8,38 +11,250 @@ use clap::Parser;
 #[command(version, about, long_about = None)]
 pub struct Args {
     /// Command to run
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub command: Option<String>,
 
     /// Arguments to pass to program
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub arg: Option<Vec<String>>,
 
-    /// Command to run for build step
-    #[arg(short('B'), long)]
+    /// Shell command string to run, instead of a command and arguments
+    #[arg(short('S'), long, conflicts_with_all = ["command", "arg"], global = true)]
+    pub shell: Option<String>,
+
+    /// Command to run for build step. Measuring fails if it fails
+    #[arg(short('B'), long, global = true)]
     pub build_command: Option<String>,
 
     /// Arguments to pass to build command
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub build_arg: Option<Vec<String>>,
 
     /// Working directory for program execution. Defaults to the `path` argument.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub working_dir: Option<PathBuf>,
 
+    /// Number of runs per benchmark and commit
+    #[arg(short, long, global = true)]
+    pub runs: Option<usize>,
+
+    /// Order of base and head runs. Interleaved schedules build
+    /// base and head in separate directories
+    #[arg(short, long, global = true)]
+    pub schedule: Option<Schedule>,
+
+    /// CPUs to pin measured programs to, e.g. `2,3`
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub cpus: Option<Vec<usize>>,
+
+    /// Nice value of measured programs, from -20 to 19
+    #[arg(long, allow_hyphen_values = true, global = true)]
+    pub nice: Option<i32>,
+
+    /// Run measured programs with real-time `SCHED_FIFO` scheduling at this priority
+    #[arg(long, global = true)]
+    pub fifo_priority: Option<i32>,
+
+    /// Shell command string for the build step. Measuring fails if it fails
+    #[arg(long, conflicts_with_all = ["build_command", "build_arg"], global = true)]
+    pub build_shell: Option<String>,
+
+    /// Shell executing command strings. Defaults to `sh`
+    #[arg(long, global = true)]
+    pub shell_program: Option<String>,
+
+    /// Whether to check that the first word of a command string is an existing program
+    #[arg(long, action, global = true)]
+    pub check_shell_command: Option<bool>,
+
+    /// Shell command string run once per commit, before the first run
+    #[arg(long, global = true)]
+    pub setup: Option<String>,
+
+    /// Shell command string run before every run, not measured
+    #[arg(long, global = true)]
+    pub prepare: Option<String>,
+
+    /// Shell command string run after every run, not measured
+    #[arg(long, global = true)]
+    pub cleanup: Option<String>,
+
+    /// Shell command string run once per commit, after the last run
+    #[arg(long, global = true)]
+    pub teardown: Option<String>,
+
+    /// Whether to drop filesystem caches before every run, to measure cold starts
+    #[arg(long, action, global = true)]
+    pub cold: Option<bool>,
+
+    /// Shell command string dropping filesystem caches in cold mode
+    #[arg(long, global = true)]
+    pub drop_caches_command: Option<String>,
+
+    /// Whether to collect performance counters of measured programs (Linux only)
+    #[arg(long, action, global = true)]
+    pub counters: Option<bool>,
+
+    /// Whether to run every measured program in a transient cgroup v2 (Linux only)
+    #[arg(long, action, global = true)]
+    pub cgroup: Option<bool>,
+
+    /// Memory limit of measured programs, e.g. `512M`. Implies `--cgroup`
+    #[arg(long, global = true)]
+    pub memory_limit: Option<String>,
+
+    /// CPU bandwidth limit of measured programs in CPUs, e.g. `1.5`. Implies `--cgroup`
+    #[arg(long, global = true)]
+    pub cpu_limit: Option<f64>,
+
+    /// How to detect outlier runs, which are excluded from the comparison
+    #[arg(long, global = true)]
+    pub outliers: Option<OutlierMethod>,
+
+    /// Threshold of the outlier detection: IQR fence factor or maximum modified z-score
+    #[arg(long, global = true)]
+    pub outlier_threshold: Option<f64>,
+
+    /// Fraction of rejected outlier runs above which a warning is shown
+    #[arg(long, global = true)]
+    pub max_outlier_fraction: Option<f64>,
+
+    /// Keep measuring until the 95% confidence interval of the wall time difference
+    /// is narrower than this many percentage points. Requires an interleaved schedule
+    #[arg(long, global = true)]
+    pub target_ci_width: Option<f64>,
+
+    /// Most runs per benchmark and commit in adaptive mode, at least the run count and 2
+    #[arg(long, global = true)]
+    pub max_runs: Option<usize>,
+
+    /// Seconds after which adaptive measuring of a benchmark stops, greater than 0
+    #[arg(long, global = true)]
+    pub time_budget: Option<f64>,
+
+    /// Pathspecs of the files affecting the benchmarks, e.g. `src,Cargo.*`.
+    /// Head reuses the results of base if none of them changed
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub paths: Option<Vec<String>>,
+
+    /// Whether to reuse results measured on a commit with an identical tree
+    /// and the same settings. With interleaved schedules, only sides without
+    /// cached results are built and measured
+    #[arg(long, action, global = true)]
+    pub cache: Option<bool>,
+
+    /// Environment variable for measured programs, as `NAME=VALUE`
+    #[arg(short, long, value_parser = parse_env_var, global = true)]
+    pub env: Option<Vec<(String, String)>>,
+
+    /// Whether to start measured programs with an empty environment
+    #[arg(long, action, global = true)]
+    pub env_clear: Option<bool>,
+
+    /// Variable kept from the environment of measured programs when it is cleared
+    #[arg(long, global = true)]
+    pub env_passthrough: Option<Vec<String>>,
+
+    /// File passed to measured programs on standard input
+    #[arg(long, global = true)]
+    pub stdin_file: Option<PathBuf>,
+
+    /// Environment variable for the build command, as `NAME=VALUE`
+    #[arg(long, value_parser = parse_env_var, global = true)]
+    pub build_env: Option<Vec<(String, String)>>,
+
+    /// Whether to start the build command with an empty environment
+    #[arg(long, action, global = true)]
+    pub build_env_clear: Option<bool>,
+
+    /// Variable kept from the environment of the build command when it is cleared
+    #[arg(long, global = true)]
+    pub build_env_passthrough: Option<Vec<String>>,
+
+    /// File passed to the build command on standard input
+    #[arg(long, global = true)]
+    pub build_stdin_file: Option<PathBuf>,
+
     /// Whether to show program output
-    #[arg(long, action)]
+    #[arg(long, action, global = true)]
     pub show_output: Option<bool>,
 
     /// Local path to git repository
-    #[arg(long, short)]
+    #[arg(long, short, global = true)]
     pub path: Option<PathBuf>,
 
-    /// Base commit in comparison
+    /// Save the results as JSON to this file, to be rendered later with `show`
+    #[arg(long, global = true)]
+    pub save: Option<PathBuf>,
+
+    /// Base commit in comparison. Refs named like a subcommand, e.g. a branch `run`,
+    /// are taken as the subcommand, use `compare` to compare them
     #[arg()]
     pub base: Option<String>,
 
     /// Head commit in comparison
     #[arg()]
     pub head: Option<String>,
+
+    /// What to do. Defaults to comparing base and head
+    #[command(subcommand)]
+    pub subcommand: Option<Subcommand>,
+}
+
+/// Subcommands, sharing the options above.
+#[derive(clap::Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Subcommand {
+    /// Compare the performance of base and head
+    Compare {
+        /// Base commit in comparison
+        base: Option<String>,
+        /// Head commit in comparison
+        head: Option<String>,
+        /// Compare the working tree, including uncommitted changes and untracked files,
+        /// with base, which defaults to HEAD
+        #[arg(long, conflicts_with = "head")]
+        working_tree: bool,
+    },
+    /// Measure the performance of a single commit
+    Run {
+        /// Commit to measure. Defaults to the working tree as it is,
+        /// including uncommitted changes, without checking out anything
+        git_ref: Option<String>,
+    },
+    /// Render results saved with `--save`
+    Show {
+        /// JSON file written by `--save`
+        file: PathBuf,
+    },
+    /// Print the configuration merged from options, environment variables and config file
+    Config,
+    /// Create a config file with commented example settings
+    Init,
+}
+
+impl Args {
+    /// Check for refs followed by a subcommand, which happens when head
+    /// is named like a subcommand, e.g. `git perfdiff main run`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error suggesting the `compare` subcommand in that case.
+    pub fn check(&self) -> Result<(), clap::Error> {
+        if self.base.is_some() && self.subcommand.is_some() {
+            return Err(Self::command().error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "refs cannot be followed by a subcommand, \
+                 use `compare` to compare refs named like subcommands, \
+                 e.g. `git perfdiff compare main run`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Parse an environment variable given as `NAME=VALUE`.
+fn parse_env_var(var: &str) -> Result<(String, String), String> {
+    var.split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("Expected `NAME=VALUE`, got `{var}`"))
 }
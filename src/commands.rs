@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::{Config, ExecutionContext, Output, CONFIG_FILE};
+use crate::measurement::Saved;
+use crate::runner;
+
+/// Starter config file written by `init`.
+const CONFIG_TEMPLATE: &str = r#"# Configuration of git-perfdiff, see `git perfdiff --help` for all options.
+
+# Shell command string building the program, run once per commit.
+# build_shell = "cargo build --release"
+
+# Shell command string to measure.
+# shell = "./target/release/my-program"
+
+# Number of runs per benchmark and commit.
+# runs = 10
+
+# Order of base and head runs: "sequential", "alternate" or "random".
+# schedule = "alternate"
+
+# Named benchmarks, measured instead of `shell`.
+# [[benchmark]]
+# name = "small"
+# shell = "./target/release/my-program --size {{ size }}"
+# params = { size = [1000, 10000] }
+
+# Custom metrics extracted from the program output.
+# [[metric]]
+# name = "throughput"
+# regex = 'throughput: (\d+)'
+# higher_is_better = true
+# Fail if head is more than 5% worse than base.
+# threshold = 5.0
+
+# Metrics specific to a benchmark are defined in its `[[benchmark.metric]]` tables.
+"#;
+
+/// Compare base and head, and print the comparison.
+/// Compares the working tree, including uncommitted changes, as head if `working_tree` is set.
+///
+/// # Errors
+///
+/// Returns an error if the configuration is invalid, measuring fails,
+/// the results cannot be rendered or saved, or a metric exceeds its threshold.
+pub fn compare(config: Config, working_tree: bool, save: Option<&Path>) -> Result<()> {
+    let mut execution_context = ExecutionContext::from_config(config)?;
+    let comparison = if working_tree {
+        runner::compare_working_tree(&mut execution_context)?
+    } else {
+        runner::compare(&execution_context)?
+    };
+    println!("{}", execution_context.render_comparison(&comparison)?);
+    let thresholds = execution_context.check_thresholds(&comparison);
+    if let Some(path) = save {
+        Saved::Comparison(comparison).save(path)?;
+    }
+    thresholds
+}
+
+/// Measure only head, and print its results.
+/// Measures the working tree as it is, including uncommitted changes, if `working_tree` is set.
+///
+/// # Errors
+///
+/// Returns an error if the configuration is invalid, measuring fails,
+/// or the results cannot be rendered or saved.
+pub fn run(mut config: Config, working_tree: bool, save: Option<&Path>) -> Result<()> {
+    // Nothing is compared, so there is no need to resolve a base reference.
+    config.base_git_ref.clone_from(&config.head_git_ref);
+    let execution_context = ExecutionContext::from_config(config)?;
+    let results = if working_tree {
+        runner::measure_working_tree(&execution_context)?
+    } else {
+        runner::measure(&execution_context)?
+    };
+    println!("{}", execution_context.render_run(&results)?);
+    if let Some(path) = save {
+        Saved::Run(results).save(path)?;
+    }
+    Ok(())
+}
+
+/// Render results saved with `--save`, using the configured output template.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be loaded, or the results cannot be rendered.
+pub fn show(config: &Config, file: &Path) -> Result<()> {
+    let saved = Saved::load(file)?;
+    let summaries: Vec<_> = match &saved {
+        Saved::Comparison(comparison) => comparison
+            .benchmarks
+            .iter()
+            .map(|benchmark| (&benchmark.params, &benchmark.base.aggregate))
+            .collect(),
+        Saved::Run(results) => results
+            .benchmarks
+            .iter()
+            .map(|benchmark| (&benchmark.params, &benchmark.summary.aggregate))
+            .collect(),
+    };
+    let metric_names: BTreeSet<&str> = summaries
+        .iter()
+        .flat_map(|(_, results)| results.metrics.keys())
+        .map(String::as_str)
+        .collect();
+    let param_names: BTreeSet<&str> = summaries
+        .iter()
+        .flat_map(|(params, _)| params.keys())
+        .map(String::as_str)
+        .collect();
+    let benchmark_metrics = config
+        .benchmarks
+        .iter()
+        .flatten()
+        .flat_map(|benchmark| &benchmark.metric);
+    let higher_is_better = config
+        .metrics
+        .iter()
+        .flatten()
+        .chain(benchmark_metrics)
+        .filter(|metric| metric.higher_is_better)
+        .map(|metric| metric.name.clone());
+    let output = Output::new(
+        config.output_template.clone(),
+        &metric_names.into_iter().collect::<Vec<_>>(),
+        &param_names.into_iter().collect::<Vec<_>>(),
+        higher_is_better,
+    )?;
+    println!("{}", output.render_saved(&saved)?);
+    Ok(())
+}
+
+/// Print the merged configuration.
+pub fn print_config(config: &Config) {
+    println!("{config:#?}");
+}
+
+/// Create a config file with commented example settings in the current directory.
+///
+/// # Errors
+///
+/// Returns an error if the config file already exists or cannot be written.
+pub fn init() -> Result<()> {
+    let mut file = std::fs::File::create_new(CONFIG_FILE).map_err(|err| {
+        if err.kind() == std::io::ErrorKind::AlreadyExists {
+            anyhow!("{CONFIG_FILE} already exists")
+        } else {
+            anyhow!("Failed to create {CONFIG_FILE}: {err}")
+        }
+    })?;
+    file.write_all(CONFIG_TEMPLATE.as_bytes())
+        .with_context(|| format!("Failed to write {CONFIG_FILE}"))?;
+    println!("Created {CONFIG_FILE}");
+    Ok(())
+}
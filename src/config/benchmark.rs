@@ -0,0 +1,601 @@
+use anyhow::{anyhow, Context, Result};
+use minijinja::{Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::{Command, HookScripts, Hooks, Scheduling, Validated};
+use crate::cgroup::Limits as CgroupLimits;
+use crate::metrics::{Definition as MetricDefinition, Extractor};
+
+/// A benchmark as defined in a `[[benchmark]]` table of the config file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Definition {
+    /// Name identifying the benchmark in the output.
+    pub name: String,
+    /// Command to run.
+    pub command: Option<String>,
+    /// Shell command string to run instead of `command`, e.g. `"./prep.sh && ./bench"`.
+    /// May use parameters as template variables.
+    pub shell: Option<String>,
+    /// Arguments to pass to the command.
+    /// May use parameters as template variables, e.g. `"{{ size }}"`.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment variables to set for the command,
+    /// in addition to the global ones.
+    /// Values may use parameters as template variables.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Whether to start the command with an empty environment.
+    /// Default is the global `env_clear` option
+    pub env_clear: Option<bool>,
+    /// Variables kept from the environment when it is cleared.
+    /// Default is the global `env_passthrough` option
+    pub env_passthrough: Option<Vec<String>>,
+    /// File passed to the command on standard input, relative to its working directory.
+    /// Default is the global `stdin_file` option
+    pub stdin_file: Option<PathBuf>,
+    /// Working directory, relative to the global working directory.
+    /// Default is the global working directory
+    pub working_dir: Option<PathBuf>,
+    /// Number of times to run the benchmark per commit.
+    /// Default is the global `runs` option
+    pub runs: Option<usize>,
+    /// Custom metrics specific to this benchmark, each a `[[benchmark.metric]]` table,
+    /// in addition to the globally defined ones. Replaces a global metric of the same name.
+    #[serde(default)]
+    pub metric: Vec<MetricDefinition>,
+    /// Parameter values, e.g. `size = [1000, 10000]`.
+    /// The benchmark is measured once for every combination of values.
+    #[serde(default)]
+    pub params: BTreeMap<String, Vec<serde_json::Value>>,
+    /// Commands run around the measurements, overriding the global ones.
+    /// May use parameters as template variables.
+    #[serde(flatten)]
+    pub hooks: HookScripts,
+}
+
+/// Settings shared by all benchmarks, used where a benchmark does not override them.
+#[allow(clippy::struct_excessive_bools)]
+pub(super) struct Defaults<'a> {
+    /// Working directory for program execution.
+    pub working_dir: &'a Path,
+    /// Whether to show program output.
+    pub show_output: bool,
+    /// Number of runs per commit.
+    pub runs: usize,
+    /// Custom metrics extracted for every benchmark.
+    pub metrics: &'a [MetricDefinition],
+    /// Environment variables set for every benchmark.
+    pub env: &'a BTreeMap<String, String>,
+    /// Whether to start programs with an empty environment.
+    pub env_clear: bool,
+    /// Variables kept from the environment when it is cleared.
+    pub env_passthrough: &'a [String],
+    /// File passed to programs on standard input.
+    pub stdin_file: Option<&'a Path>,
+    /// Shell executing command strings.
+    pub shell_program: &'a str,
+    /// Whether to check that the first word of a command string is an existing program.
+    pub check_shell_command: bool,
+    /// Commands run around the measurements.
+    pub hooks: &'a HookScripts,
+    /// Shell command string dropping filesystem caches before every run, in cold mode.
+    pub drop_caches: Option<&'a str>,
+    /// Whether to collect performance counters.
+    pub counters: bool,
+    /// Limits of the transient cgroup of each measured run, if running in one.
+    pub cgroup: Option<&'a CgroupLimits>,
+    /// CPU affinity and priority of the measured programs.
+    pub scheduling: &'a Scheduling,
+}
+
+/// A validated benchmark, ready to be measured.
+pub struct Benchmark {
+    /// Name identifying the benchmark in the output,
+    /// including parameter values if there are any.
+    pub name: String,
+    /// Name of the benchmark definition this was created from.
+    pub group: String,
+    /// Parameter values of this case of the benchmark.
+    pub params: BTreeMap<String, String>,
+    /// The command to measure.
+    pub command: Command<Validated>,
+    /// Number of times to run the command per commit.
+    pub runs: usize,
+    /// Custom metrics to extract from the command output.
+    pub metrics: Vec<Extractor>,
+    /// Commands run around the measurements.
+    pub hooks: Hooks,
+}
+
+/// Display a parameter value, without quotes for strings.
+fn param_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Name of a case of a benchmark, e.g. `sort[size=10,threads=1]`.
+fn case_name(name: &str, case: &BTreeMap<String, String>) -> String {
+    if case.is_empty() {
+        return name.to_string();
+    }
+    let values: Vec<String> = case
+        .iter()
+        .map(|(param, value)| format!("{param}={value}"))
+        .collect();
+    format!("{name}[{}]", values.join(","))
+}
+
+/// All combinations of parameter values.
+/// Without parameters, there is a single empty combination.
+fn combinations(
+    params: &BTreeMap<String, Vec<serde_json::Value>>,
+) -> Vec<BTreeMap<String, String>> {
+    params
+        .iter()
+        .fold(vec![BTreeMap::new()], |cases, (param, values)| {
+            cases
+                .iter()
+                .flat_map(|case| {
+                    values.iter().map(move |value| {
+                        let mut case = case.clone();
+                        case.insert(param.clone(), param_to_string(value));
+                        case
+                    })
+                })
+                .collect()
+        })
+}
+
+/// Global metrics not replaced by those of a benchmark, followed by the latter.
+fn with_global_metrics(
+    own: Vec<MetricDefinition>,
+    global: &[MetricDefinition],
+) -> Vec<MetricDefinition> {
+    let mut metrics: Vec<MetricDefinition> = global
+        .iter()
+        .filter(|metric| !own.iter().any(|own| own.name == metric.name))
+        .cloned()
+        .collect();
+    metrics.extend(own);
+    metrics
+}
+
+impl Definition {
+    /// A benchmark running a single command, named after its command line.
+    pub(super) fn from_command(command: String, args: Vec<String>) -> Self {
+        let name = std::iter::once(&command)
+            .chain(&args)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            name,
+            command: Some(command),
+            shell: None,
+            args,
+            env: BTreeMap::new(),
+            env_clear: None,
+            env_passthrough: None,
+            stdin_file: None,
+            working_dir: None,
+            runs: None,
+            metric: Vec::new(),
+            params: BTreeMap::new(),
+            hooks: HookScripts::default(),
+        }
+    }
+
+    /// A benchmark running a single shell command string, named after it.
+    pub(super) fn from_shell(script: String) -> Self {
+        Self {
+            name: script.clone(),
+            command: None,
+            shell: Some(script),
+            ..Self::from_command(String::new(), Vec::new())
+        }
+    }
+
+    /// Check options that are invalid regardless of the defaults.
+    fn check(&self) -> Result<()> {
+        let name = &self.name;
+        if self.command.is_some() == self.shell.is_some() {
+            return Err(anyhow!(
+                "Benchmark `{name}` must specify exactly one of `command` and `shell`"
+            ));
+        }
+        if self.shell.is_some() && !self.args.is_empty() {
+            return Err(anyhow!(
+                "Benchmark `{name}` cannot pass `args` to a `shell` command string"
+            ));
+        }
+        if let Some((param, _)) = self.params.iter().find(|(_, values)| values.is_empty()) {
+            return Err(anyhow!(
+                "Parameter `{param}` of benchmark `{name}` has no values"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate the benchmark, filling in unset options from the defaults.
+    /// Returns one benchmark for every combination of parameter values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command, the parameters or any of the metrics fail to validate.
+    pub(super) fn validate(self, defaults: &Defaults) -> Result<Vec<Benchmark>> {
+        self.check()?;
+        let Self {
+            name,
+            command,
+            shell,
+            args,
+            env,
+            env_clear,
+            env_passthrough,
+            stdin_file,
+            working_dir,
+            runs,
+            metric,
+            params,
+            hooks,
+        } = self;
+        let runs = runs.unwrap_or(defaults.runs);
+        if runs == 0 {
+            return Err(anyhow!("Benchmark `{name}` must run at least once"));
+        }
+        let working_dir = working_dir.map_or_else(
+            || defaults.working_dir.to_path_buf(),
+            |dir| defaults.working_dir.join(dir),
+        );
+        let env: BTreeMap<String, String> = defaults.env.clone().into_iter().chain(env).collect();
+        let env_clear = env_clear.unwrap_or(defaults.env_clear);
+        let env_passthrough = env_passthrough.unwrap_or_else(|| defaults.env_passthrough.to_vec());
+        let stdin_file = stdin_file.or_else(|| defaults.stdin_file.map(Path::to_path_buf));
+        let metrics = with_global_metrics(metric, defaults.metrics);
+        let hooks = hooks.or(defaults.hooks);
+
+        let mut engine = Environment::new();
+        engine.set_undefined_behavior(UndefinedBehavior::Strict);
+
+        combinations(&params)
+            .into_iter()
+            .map(|case| {
+                let case_name = case_name(&name, &case);
+                let render = |text: &String| {
+                    if params.is_empty() {
+                        return Ok(text.clone());
+                    }
+                    engine.render_str(text, &case).with_context(|| {
+                        format!("Benchmark `{case_name}`: invalid template {text:?}")
+                    })
+                };
+                let args = args.iter().map(render).collect::<Result<_>>()?;
+                let env = env
+                    .iter()
+                    .map(|(var, value)| Ok((var.clone(), render(value)?)))
+                    .collect::<Result<_>>()?;
+                let command = match (&command, &shell) {
+                    (_, Some(script)) => Command::shell(
+                        defaults.shell_program.to_string(),
+                        render(script)?,
+                        working_dir.clone(),
+                        defaults.show_output,
+                    )
+                    .with_script_check(defaults.check_shell_command),
+                    _ => Command::new(
+                        command.clone().unwrap_or_default(),
+                        args,
+                        working_dir.clone(),
+                        defaults.show_output,
+                    ),
+                };
+                let command = command
+                    .with_env(env)
+                    .with_env_clear(env_clear, env_passthrough.clone())
+                    .with_stdin_file(stdin_file.clone())
+                    .with_scheduling(defaults.scheduling.clone())
+                    .with_counters(defaults.counters)
+                    .with_cgroup(defaults.cgroup.cloned())
+                    .validate()
+                    .map_err(|err| anyhow!("Benchmark `{case_name}`: {err}"))?;
+                let metrics = metrics
+                    .iter()
+                    .cloned()
+                    .map(Extractor::try_from)
+                    .collect::<Result<_>>()?;
+                let hooks = Hooks::validate(
+                    hooks.try_map(render)?,
+                    defaults.drop_caches,
+                    &command,
+                    defaults.shell_program,
+                    defaults.check_shell_command,
+                )
+                .map_err(|err| anyhow!("Benchmark `{case_name}`: {err}"))?;
+                Ok(Benchmark {
+                    name: case_name,
+                    group: name.clone(),
+                    params: case,
+                    command,
+                    runs,
+                    metrics,
+                    hooks,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Validate all benchmark definitions, expanding their parameters.
+///
+/// # Errors
+///
+/// Returns an error if any benchmark fails to validate,
+/// or if two benchmarks share the same name.
+pub(super) fn validate_all(
+    definitions: Vec<Definition>,
+    defaults: &Defaults,
+) -> Result<Vec<Benchmark>> {
+    let benchmarks: Vec<Benchmark> = definitions
+        .into_iter()
+        .map(|definition| definition.validate(defaults))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    let mut names = HashSet::new();
+    if let Some(duplicate) = benchmarks
+        .iter()
+        .find(|benchmark| !names.insert(&benchmark.name))
+    {
+        return Err(anyhow!("Duplicate benchmark name `{}`", duplicate.name));
+    }
+    Ok(benchmarks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_all, Command, Defaults, Definition, HookScripts, Scheduling, Validated};
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    static ENV: BTreeMap<String, String> = BTreeMap::new();
+
+    static HOOKS: HookScripts = HookScripts {
+        setup: None,
+        prepare: None,
+        cleanup: None,
+        teardown: None,
+    };
+
+    static SCHEDULING: Scheduling = Scheduling {
+        cpus: None,
+        nice: None,
+        fifo_priority: None,
+    };
+
+    fn defaults() -> Defaults<'static> {
+        Defaults {
+            working_dir: Path::new("/"),
+            show_output: false,
+            runs: 1,
+            metrics: &[],
+            env: &ENV,
+            env_clear: false,
+            env_passthrough: &[],
+            stdin_file: None,
+            shell_program: "sh",
+            check_shell_command: false,
+            hooks: &HOOKS,
+            drop_caches: None,
+            counters: false,
+            cgroup: None,
+            scheduling: &SCHEDULING,
+        }
+    }
+
+    fn definition(toml: &str) -> Definition {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn parameter_matrix() {
+        let benchmark = definition(
+            r#"
+                name = "sort"
+                command = "/bin/sh"
+                args = ["sort.sh", "{{ size }}"]
+                env = { THREADS = "{{ threads }}" }
+                params = { size = [10, 100], threads = ["1", "2"] }
+            "#,
+        );
+        let cases = validate_all(vec![benchmark], &defaults()).unwrap();
+        let names: Vec<&str> = cases.iter().map(|case| case.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "sort[size=10,threads=1]",
+                "sort[size=10,threads=2]",
+                "sort[size=100,threads=1]",
+                "sort[size=100,threads=2]",
+            ]
+        );
+        assert!(cases.iter().all(|case| case.group == "sort"));
+        assert_eq!(cases[2].command.args, ["sort.sh", "100"]);
+        assert_eq!(cases[3].command.env["THREADS"], "2");
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let undefined = definition(
+            r#"
+                name = "sort"
+                command = "/bin/sh"
+                args = ["{{ count }}"]
+                params = { size = [10] }
+            "#,
+        );
+        assert!(validate_all(vec![undefined], &defaults()).is_err());
+        let empty = definition(
+            r#"
+                name = "sort"
+                command = "/bin/sh"
+                params = { size = [] }
+            "#,
+        );
+        assert!(validate_all(vec![empty], &defaults()).is_err());
+    }
+
+    #[test]
+    fn environment() {
+        let global_env = [("A", "1"), ("B", "2")]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .into();
+        let passthrough = ["PATH".to_string()];
+        let defaults = Defaults {
+            env: &global_env,
+            env_clear: true,
+            env_passthrough: &passthrough,
+            ..defaults()
+        };
+        let benchmark = definition(
+            r#"
+                name = "env"
+                command = "/bin/sh"
+                env = { B = "3" }
+            "#,
+        );
+        let cases = validate_all(vec![benchmark], &defaults).unwrap();
+        let command = &cases[0].command;
+        assert_eq!(command.env["A"], "1");
+        assert_eq!(command.env["B"], "3");
+        assert!(command.env_clear);
+        assert_eq!(command.env_passthrough, passthrough);
+
+        let missing_input = definition(
+            r#"
+                name = "env"
+                command = "/bin/sh"
+                stdin_file = "does-not-exist"
+            "#,
+        );
+        assert!(validate_all(vec![missing_input], &defaults).is_err());
+    }
+
+    #[test]
+    fn shell_command() {
+        let benchmark = definition(
+            r#"
+                name = "pipeline"
+                shell = "seq {{ n }} | sort -r"
+                params = { n = [10] }
+            "#,
+        );
+        let cases = validate_all(vec![benchmark], &defaults()).unwrap();
+        let command = &cases[0].command;
+        assert_eq!(command.command, "sh");
+        assert_eq!(command.args, ["-c", "seq 10 | sort -r"]);
+
+        let both = definition(
+            r#"
+                name = "both"
+                command = "/bin/sh"
+                shell = "true"
+            "#,
+        );
+        assert!(validate_all(vec![both], &defaults()).is_err());
+        let with_args = definition(
+            r#"
+                name = "args"
+                shell = "true"
+                args = ["x"]
+            "#,
+        );
+        assert!(validate_all(vec![with_args], &defaults()).is_err());
+    }
+
+    #[test]
+    fn hooks() {
+        let global_hooks = HookScripts {
+            setup: Some("mkdir -p data".to_string()),
+            prepare: Some("rm -f data/db".to_string()),
+            ..HookScripts::default()
+        };
+        let defaults = Defaults {
+            hooks: &global_hooks,
+            ..defaults()
+        };
+        let benchmark = definition(
+            r#"
+                name = "db"
+                command = "/bin/sh"
+                prepare = "truncate -s {{ size }} data/db"
+                params = { size = [1024] }
+            "#,
+        );
+        let cases = validate_all(vec![benchmark], &defaults).unwrap();
+        let hooks = &cases[0].hooks;
+        let args = |hook: &Option<Command<Validated>>| hook.as_ref().unwrap().args.clone();
+        assert_eq!(args(&hooks.setup), ["-c", "mkdir -p data"]);
+        assert_eq!(args(&hooks.prepare), ["-c", "truncate -s 1024 data/db"]);
+        assert!(hooks.cleanup.is_none() && hooks.teardown.is_none());
+    }
+
+    #[test]
+    fn metrics() {
+        let global_metrics = definition(
+            r#"
+                name = "global"
+                [[metric]]
+                name = "ops"
+                regex = 'ops: (\d+)'
+                [[metric]]
+                name = "latency"
+                regex = 'latency: (\d+)'
+            "#,
+        )
+        .metric;
+        let defaults = Defaults {
+            metrics: &global_metrics,
+            ..defaults()
+        };
+        let benchmark = definition(
+            r#"
+                name = "db"
+                command = "/bin/sh"
+                [[metric]]
+                name = "ops"
+                json_path = "ops"
+                threshold = 5.0
+                [[metric]]
+                name = "rows"
+                regex = 'rows: (\d+)'
+            "#,
+        );
+        let cases = validate_all(vec![benchmark], &defaults).unwrap();
+        let metrics: Vec<(&str, Option<f64>)> = cases[0]
+            .metrics
+            .iter()
+            .map(|metric| (metric.name.as_str(), metric.threshold))
+            .collect();
+        assert_eq!(
+            metrics,
+            [("latency", None), ("ops", Some(5.0)), ("rows", None)]
+        );
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let benchmark = definition(
+            r#"
+                name = "sort"
+                command = "/bin/sh"
+            "#,
+        );
+        assert!(validate_all(vec![benchmark.clone(), benchmark], &defaults()).is_err());
+    }
+}
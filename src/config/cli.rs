@@ -1,5 +1,5 @@
 use super::Config;
-use crate::cli::Args;
+use crate::cli::{Args, Subcommand};
 
 impl From<Args> for Config {
     fn from(args: Args) -> Self {
@@ -9,17 +9,90 @@ impl From<Args> for Config {
             build_command,
             build_arg,
             working_dir,
+            runs,
+            schedule,
+            cpus,
+            nice,
+            fifo_priority,
+            env,
+            env_clear,
+            env_passthrough,
+            stdin_file,
+            build_env,
+            build_env_clear,
+            build_env_passthrough,
+            build_stdin_file,
+            shell,
+            build_shell,
+            shell_program,
+            check_shell_command,
+            setup,
+            prepare,
+            cleanup,
+            teardown,
+            cold,
+            drop_caches_command,
+            counters,
+            cgroup,
+            memory_limit,
+            cpu_limit,
+            outliers,
+            outlier_threshold,
+            max_outlier_fraction,
+            target_ci_width,
+            max_runs,
+            time_budget,
+            paths,
+            cache,
             show_output,
             path,
+            save: _,
             base,
             head,
+            subcommand,
         } = args;
+        let (base, head) = git_refs(base, head, subcommand);
         Self {
             command,
             arg,
             build_command,
             build_arg,
             working_dir,
+            runs,
+            schedule,
+            cpus,
+            nice,
+            fifo_priority,
+            env: env.map(|vars| vars.into_iter().collect()),
+            env_clear,
+            env_passthrough,
+            stdin_file,
+            build_env: build_env.map(|vars| vars.into_iter().collect()),
+            build_env_clear,
+            build_env_passthrough,
+            build_stdin_file,
+            shell,
+            build_shell,
+            shell_program,
+            check_shell_command,
+            setup,
+            prepare,
+            cleanup,
+            teardown,
+            cold,
+            drop_caches_command,
+            counters,
+            cgroup,
+            memory_limit,
+            cpu_limit,
+            outliers,
+            outlier_threshold,
+            max_outlier_fraction,
+            target_ci_width,
+            max_runs,
+            time_budget,
+            paths,
+            cache,
             show_output,
             git_path: path,
             base_git_ref: base,
@@ -28,3 +101,78 @@ impl From<Args> for Config {
         }
     }
 }
+
+/// Base and head references, where those given to a subcommand
+/// take precedence over the top-level ones.
+fn git_refs(
+    base: Option<String>,
+    head: Option<String>,
+    subcommand: Option<Subcommand>,
+) -> (Option<String>, Option<String>) {
+    match subcommand {
+        Some(Subcommand::Compare {
+            base,
+            working_tree: true,
+            ..
+        }) => (base.or_else(|| Some("HEAD".to_string())), None),
+        Some(Subcommand::Compare {
+            base: Some(base),
+            head,
+            ..
+        }) => (Some(base), head),
+        Some(Subcommand::Run {
+            git_ref: Some(git_ref),
+        }) => (None, Some(git_ref)),
+        _ => (base, head),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::cli::Args;
+    use clap::Parser;
+
+    fn refs(args: &[&str]) -> (Option<String>, Option<String>) {
+        let config: Config = Args::try_parse_from(args).unwrap().into();
+        (config.base_git_ref, config.head_git_ref)
+    }
+
+    #[test]
+    fn subcommand_refs() {
+        let expected = (Some("main".to_string()), Some("HEAD".to_string()));
+        assert_eq!(refs(&["git-perfdiff", "main", "HEAD"]), expected);
+        assert_eq!(refs(&["git-perfdiff", "compare", "main", "HEAD"]), expected);
+        assert_eq!(
+            refs(&["git-perfdiff", "compare", "--working-tree"]),
+            (Some("HEAD".to_string()), None)
+        );
+        assert_eq!(
+            refs(&["git-perfdiff", "run", "v1", "--runs", "3"]),
+            (None, Some("v1".to_string()))
+        );
+        assert_eq!(
+            refs(&["git-perfdiff", "show", "results.json"]),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn refs_named_like_subcommands() {
+        // A leading ref named like a subcommand is taken as the subcommand.
+        assert_eq!(
+            refs(&["git-perfdiff", "run", "HEAD"]),
+            (None, Some("HEAD".to_string()))
+        );
+        // With `compare`, any names are refs.
+        assert_eq!(
+            refs(&["git-perfdiff", "compare", "run", "show"]),
+            (Some("run".to_string()), Some("show".to_string()))
+        );
+        // Otherwise, a subcommand after a ref is rejected instead of ignoring the ref.
+        let args = Args::try_parse_from(["git-perfdiff", "main", "run"]).unwrap();
+        assert!(args.check().is_err());
+        let args = Args::try_parse_from(["git-perfdiff", "main", "HEAD"]).unwrap();
+        assert!(args.check().is_ok());
+    }
+}
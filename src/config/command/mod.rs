@@ -1,15 +1,30 @@
+use std::collections::BTreeMap;
+use std::fs::File;
 use std::marker::PhantomData;
-use std::path::PathBuf;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use which::which;
 
+use crate::cgroup::{self, Limits as CgroupLimits};
+
 /// Validation for commands
 mod validation;
 
+/// CPU affinity and scheduling priority
+mod scheduling;
+
+/// Command strings executed by a shell
+mod shell;
+
+pub use scheduling::{Scheduling, PERFORMANCE_GOVERNOR};
+pub use shell::DEFAULT_SHELL;
 pub use validation::Validated;
 use validation::{Error, NotValidated, State};
 
 /// Everything required to execute an external command.
+#[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config<S: State> {
     /// The command to execute.
     pub command: String,
@@ -19,6 +34,26 @@ pub struct Config<S: State> {
     pub working_dir: PathBuf,
     /// Whether to print output to stdout.
     pub show_output: bool,
+    /// Command string passed to `command` as a shell, if any.
+    pub script: Option<String>,
+    /// Whether validation checks that the first word of `script` is an existing program.
+    pub check_script: bool,
+    /// Environment variables set for the program.
+    pub env: BTreeMap<String, String>,
+    /// Whether to start the program with an empty environment,
+    /// apart from `env` and `env_passthrough`.
+    pub env_clear: bool,
+    /// Variables kept from the current environment if `env_clear` is set.
+    pub env_passthrough: Vec<String>,
+    /// File passed to the program on standard input,
+    /// relative to the working directory.
+    pub stdin_file: Option<PathBuf>,
+    /// How the operating system schedules the program.
+    pub scheduling: Scheduling,
+    /// Whether to collect performance counters when measuring the program.
+    pub counters: bool,
+    /// Limits of the transient cgroup the program runs in when measured, if any.
+    pub cgroup: Option<CgroupLimits>,
     /// Phantom data to allow the state to be set.
     pub(super) _marker: PhantomData<S>,
 }
@@ -39,6 +74,37 @@ impl Config<NotValidated> {
         if !self.working_dir.try_exists().unwrap_or(false) {
             return Err(Error::WorkingDirNotFound);
         }
+        if let Some(script) = self.script.as_ref().filter(|_| self.check_script) {
+            if !shell::first_word_exists(script, &self.working_dir) {
+                return Err(Error::ScriptCommandNotFound);
+            }
+        }
+        if !self.scheduling.is_valid() {
+            return Err(Error::InvalidScheduling);
+        }
+        let valid_name = |name: &String| !name.is_empty() && !name.contains(['=', '\0']);
+        if !self.env.keys().all(valid_name)
+            || self.env.values().any(|value| value.contains('\0'))
+            || !self.env_passthrough.iter().all(valid_name)
+        {
+            return Err(Error::InvalidEnvVar);
+        }
+        if !self.env_clear && !self.env_passthrough.is_empty() {
+            return Err(Error::PassthroughWithoutEnvClear);
+        }
+        if let Some(stdin_file) = &self.stdin_file {
+            if !self.working_dir.join(stdin_file).is_file() {
+                return Err(Error::StdinFileNotFound);
+            }
+        }
+        if let Some(limits) = &self.cgroup {
+            if !limits.is_valid() {
+                return Err(Error::InvalidCgroupLimits);
+            }
+            if !cgroup::is_available() {
+                return Err(Error::CgroupUnavailable);
+            }
+        }
         Ok(self.transition())
     }
 
@@ -55,21 +121,147 @@ impl Config<NotValidated> {
             args,
             show_output,
             working_dir,
+            script: None,
+            check_script: false,
+            env: BTreeMap::new(),
+            env_clear: false,
+            env_passthrough: Vec::new(),
+            stdin_file: None,
+            scheduling: Scheduling {
+                cpus: None,
+                nice: None,
+                fifo_priority: None,
+            },
+            counters: false,
+            cgroup: None,
             _marker: PhantomData,
         }
     }
+
+    /// Create a config object running a command string with a shell,
+    /// as `<shell> -c <script>`.
+    #[must_use]
+    pub(crate) fn shell(
+        shell: String,
+        script: String,
+        working_dir: PathBuf,
+        show_output: bool,
+    ) -> Self {
+        let mut config = Self::new(
+            shell,
+            vec!["-c".to_string(), script.clone()],
+            working_dir,
+            show_output,
+        );
+        config.script = Some(script);
+        config
+    }
+
+    /// Check that the first word of the command string is an existing program
+    /// when validating.
+    #[must_use]
+    pub(crate) const fn with_script_check(mut self, check_script: bool) -> Self {
+        self.check_script = check_script;
+        self
+    }
+
+    /// Set environment variables for the program.
+    #[must_use]
+    pub(crate) fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Start the program with an empty environment,
+    /// apart from explicitly set variables and the `passthrough` ones.
+    #[must_use]
+    pub(crate) fn with_env_clear(mut self, env_clear: bool, passthrough: Vec<String>) -> Self {
+        self.env_clear = env_clear;
+        self.env_passthrough = passthrough;
+        self
+    }
+
+    /// Pass a file to the program on standard input.
+    #[must_use]
+    pub(crate) fn with_stdin_file(mut self, stdin_file: Option<PathBuf>) -> Self {
+        self.stdin_file = stdin_file;
+        self
+    }
+
+    /// Collect performance counters when measuring the program.
+    #[must_use]
+    pub(crate) const fn with_counters(mut self, counters: bool) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Run the program in a transient cgroup with the given limits when measuring it.
+    #[must_use]
+    pub(crate) fn with_cgroup(mut self, cgroup: Option<CgroupLimits>) -> Self {
+        self.cgroup = cgroup;
+        self
+    }
+
+    /// Set CPU affinity and scheduling priority for the program.
+    #[must_use]
+    pub(crate) fn with_scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
 }
 
 impl Config<Validated> {
+    /// Copy of the command with its working directory moved from within `from`
+    /// to the same relative location within `to`.
+    /// Working directories outside of `from` are kept.
+    /// Relative working directories are resolved against the current directory first.
     #[must_use]
+    pub fn relocate(&self, from: &Path, to: &Path) -> Self {
+        let current = self
+            .working_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.working_dir.clone());
+        let working_dir = current
+            .strip_prefix(from)
+            .map_or_else(|_| self.working_dir.clone(), |relative| to.join(relative));
+        Self {
+            working_dir,
+            ..self.clone()
+        }
+    }
+
     /// Construct an executable command from the configuration.
-    pub fn to_command(&self) -> Command {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the standard input file cannot be opened.
+    pub fn to_command(&self) -> std::io::Result<Command> {
         let mut command = Command::new(&self.command);
         command.args(&self.args);
         command.current_dir(&self.working_dir);
+        if self.env_clear {
+            command.env_clear();
+            for name in &self.env_passthrough {
+                if let Some(value) = std::env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        command.envs(&self.env);
+        if let Some(stdin_file) = &self.stdin_file {
+            command.stdin(File::open(self.working_dir.join(stdin_file))?);
+        }
         if !self.show_output {
             command.stdout(Stdio::null());
         }
-        command
+        if !self.scheduling.is_default() {
+            let scheduling = self.scheduling.clone();
+            // SAFETY: Applying the scheduling options only makes system calls,
+            // it neither allocates nor takes locks.
+            unsafe {
+                command.pre_exec(move || scheduling.apply());
+            }
+        }
+        Ok(command)
     }
 }
@@ -0,0 +1,170 @@
+use std::io;
+use std::path::Path;
+
+/// Directory describing the CPUs of the system.
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+/// Number of CPUs that fit into a CPU set.
+const MAX_CPUS: usize = 1024;
+
+/// Frequency governor keeping the CPU at its highest frequency.
+pub const PERFORMANCE_GOVERNOR: &str = "performance";
+
+/// How the operating system schedules a command.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scheduling {
+    /// CPUs the command is pinned to.
+    /// Default is all CPUs
+    pub cpus: Option<Vec<usize>>,
+    /// Nice value, from -20 (highest priority) to 19 (lowest priority).
+    pub nice: Option<i32>,
+    /// Real-time `SCHED_FIFO` priority, from 1 to 99.
+    /// Usually requires elevated privileges.
+    pub fifo_priority: Option<i32>,
+}
+
+impl Scheduling {
+    /// Whether the command is scheduled like any other process.
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Frequency governors of the CPUs the command may run on,
+    /// joined by `,` if they differ.
+    /// Returns `None` if the governor cannot be read, e.g. on non-Linux systems.
+    #[must_use]
+    pub fn cpu_governor(&self) -> Option<String> {
+        let governor = |cpu: &str| {
+            let path = Path::new(CPU_DIR)
+                .join(cpu)
+                .join("cpufreq/scaling_governor");
+            std::fs::read_to_string(path)
+                .ok()
+                .map(|governor| governor.trim().to_string())
+        };
+        let mut governors: Vec<String> = match &self.cpus {
+            Some(cpus) => cpus
+                .iter()
+                .filter_map(|cpu| governor(&format!("cpu{cpu}")))
+                .collect(),
+            None => std::fs::read_dir(CPU_DIR)
+                .ok()?
+                .filter_map(|entry| {
+                    let name = entry.ok()?.file_name().into_string().ok()?;
+                    let index = name.strip_prefix("cpu")?;
+                    if index.is_empty() || !index.bytes().all(|byte| byte.is_ascii_digit()) {
+                        return None;
+                    }
+                    governor(&name)
+                })
+                .collect(),
+        };
+        governors.sort();
+        governors.dedup();
+        (!governors.is_empty()).then(|| governors.join(","))
+    }
+
+    /// Apply the scheduling options to the current process.
+    /// Meant to be called in a freshly forked child, before executing the command.
+    ///
+    /// # Errors
+    ///
+    /// Returns the OS error of the first option that could not be applied.
+    #[cfg(target_os = "linux")]
+    pub(super) fn apply(&self) -> io::Result<()> {
+        /// Turn the return value of a libc call into a result.
+        fn check(result: libc::c_int) -> io::Result<()> {
+            if result == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+        // SAFETY: All calls only affect the calling process (pid 0)
+        // and receive pointers to initialized values on the stack.
+        unsafe {
+            if let Some(cpus) = &self.cpus {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                for &cpu in cpus {
+                    libc::CPU_SET(cpu, &mut set);
+                }
+                check(libc::sched_setaffinity(
+                    0,
+                    std::mem::size_of::<libc::cpu_set_t>(),
+                    &raw const set,
+                ))?;
+            }
+            if let Some(nice) = self.nice {
+                check(libc::setpriority(libc::PRIO_PROCESS, 0, nice))?;
+            }
+            if let Some(priority) = self.fifo_priority {
+                let param = libc::sched_param {
+                    sched_priority: priority,
+                };
+                check(libc::sched_setscheduler(
+                    0,
+                    libc::SCHED_FIFO,
+                    &raw const param,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scheduling options are only supported on Linux.
+    ///
+    /// # Errors
+    ///
+    /// Always returns an error.
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn apply(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "CPU affinity and scheduling priority are only supported on Linux",
+        ))
+    }
+
+    /// Check that the options are within the ranges accepted by the OS.
+    pub(super) fn is_valid(&self) -> bool {
+        self.cpus
+            .as_ref()
+            .is_none_or(|cpus| !cpus.is_empty() && cpus.iter().all(|&cpu| cpu < MAX_CPUS))
+            && self.nice.is_none_or(|nice| (-20..=19).contains(&nice))
+            && self
+                .fifo_priority
+                .is_none_or(|priority| (1..=99).contains(&priority))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduling;
+
+    #[test]
+    fn validation() {
+        assert!(Scheduling::default().is_valid());
+        let valid = Scheduling {
+            cpus: Some(vec![0, 1]),
+            nice: Some(-20),
+            fifo_priority: Some(99),
+        };
+        assert!(valid.is_valid());
+        for invalid in [
+            Scheduling {
+                cpus: Some(Vec::new()),
+                ..Scheduling::default()
+            },
+            Scheduling {
+                nice: Some(20),
+                ..Scheduling::default()
+            },
+            Scheduling {
+                fifo_priority: Some(0),
+                ..Scheduling::default()
+            },
+        ] {
+            assert!(!invalid.is_valid());
+        }
+    }
+}
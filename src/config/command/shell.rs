@@ -0,0 +1,61 @@
+use std::path::Path;
+use which::which;
+
+/// Shell used for command strings if none is configured.
+pub const DEFAULT_SHELL: &str = "sh";
+
+/// Commands built into common shells, which cannot be found on the `PATH`.
+const BUILTINS: &[&str] = &[
+    ".", ":", "[", "alias", "cd", "command", "eval", "exec", "export", "false", "set", "source",
+    "test", "true", "ulimit", "umask", "unset",
+];
+
+/// Whether a word assigns a variable, e.g. `RUST_LOG=debug`.
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// The program a command string starts with, skipping variable assignments.
+/// Quoting and expansions are not interpreted.
+pub(super) fn first_word(script: &str) -> Option<&str> {
+    script.split_whitespace().find(|word| !is_assignment(word))
+}
+
+/// Whether the first word of a command string names an existing program or builtin.
+/// Paths are resolved against the working directory.
+pub(super) fn first_word_exists(script: &str, working_dir: &Path) -> bool {
+    first_word(script).is_some_and(|word| {
+        if word.contains('/') {
+            working_dir.join(word).is_file()
+        } else {
+            BUILTINS.contains(&word) || which(word).is_ok()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_word, first_word_exists};
+    use std::path::Path;
+
+    #[test]
+    fn first_words() {
+        assert_eq!(first_word("cargo build && ./prep.sh"), Some("cargo"));
+        assert_eq!(
+            first_word("  RUST_LOG=debug  ./bench --fast"),
+            Some("./bench")
+        );
+        assert_eq!(first_word("A=1 B=2"), None);
+        assert!(first_word_exists("sh -c true", Path::new("/")));
+        assert!(first_word_exists("cd /tmp && ls", Path::new("/")));
+        assert!(!first_word_exists(
+            "no-such-program-xyz --flag",
+            Path::new("/")
+        ));
+        assert!(!first_word_exists("./missing.sh", Path::new("/")));
+    }
+}
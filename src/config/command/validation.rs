@@ -6,9 +6,11 @@ use std::{fmt::Display, marker::PhantomData};
 pub trait State {}
 
 /// Represent a not yet validated command.
+#[derive(Clone)]
 pub struct NotValidated;
 
 /// Represent a successfully validated command.
+#[derive(Clone)]
 pub struct Validated;
 
 impl State for NotValidated {}
@@ -19,16 +21,46 @@ impl State for Validated {}
 pub enum Error {
     /// The command was not found on the PATH.
     CommandNotFound,
+    /// The first word of a shell command string is not an existing program.
+    ScriptCommandNotFound,
     /// The directory to execute does not exist.
     /// Symbolic links are followed in the verification of this.
     WorkingDirNotFound,
+    /// The CPU set, nice value or real-time priority is out of range.
+    InvalidScheduling,
+    /// An environment variable name is empty or contains `=` or a null byte,
+    /// or a value contains a null byte.
+    InvalidEnvVar,
+    /// Variables are passed through without clearing the environment.
+    PassthroughWithoutEnvClear,
+    /// The file for standard input does not exist.
+    StdinFileNotFound,
+    /// The memory or CPU limit is malformed or out of range.
+    InvalidCgroupLimits,
+    /// The program should run in a cgroup, but cgroup v2 is unavailable.
+    CgroupUnavailable,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::CommandNotFound => f.write_str("Command not found"),
+            Self::ScriptCommandNotFound => f.write_str("Command in shell command string not found"),
             Self::WorkingDirNotFound => f.write_str("Working directory not found"),
+            Self::InvalidScheduling => f.write_str(
+                "Invalid scheduling: CPUs must be non-empty, nice within -20..=19 \
+                 and FIFO priority within 1..=99",
+            ),
+            Self::InvalidEnvVar => f.write_str("Invalid environment variable"),
+            Self::PassthroughWithoutEnvClear => {
+                f.write_str("`env_passthrough` requires `env_clear`")
+            }
+            Self::StdinFileNotFound => f.write_str("Standard input file not found"),
+            Self::InvalidCgroupLimits => f.write_str(
+                "Invalid cgroup limits: memory must be a number of bytes, \
+                 optionally with a `K`, `M` or `G` suffix, and CPUs at least 0.01",
+            ),
+            Self::CgroupUnavailable => f.write_str("cgroup v2 is unavailable"),
         }
     }
 }
@@ -44,6 +76,15 @@ impl<S: State> Config<S> {
             args: self.args,
             working_dir: self.working_dir,
             show_output: self.show_output,
+            script: self.script,
+            check_script: self.check_script,
+            env: self.env,
+            env_clear: self.env_clear,
+            env_passthrough: self.env_passthrough,
+            stdin_file: self.stdin_file,
+            scheduling: self.scheduling,
+            counters: self.counters,
+            cgroup: self.cgroup,
             _marker: PhantomData,
         }
     }
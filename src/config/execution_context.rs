@@ -1,24 +1,52 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::{anyhow, Result};
+use serde::Serialize;
 
-use crate::config::{Command, Formatter, Validated};
+use crate::adaptive::Adaptive;
+use crate::cache::Cache;
+use crate::cgroup::{Limits as CgroupLimits, Subtree as CgroupSubtree};
+use crate::config::{
+    Benchmark, BenchmarkDefinition, Command, HookScripts, Output, Scheduling, Validated,
+};
 use crate::git::Context as GitContext;
 use crate::git::DiffTargets;
-use crate::measurement::Results;
+use crate::measurement::{Comparison, RefResults};
+use crate::metrics::Definition as MetricDefinition;
+use crate::outliers::Outliers;
+use crate::schedule::Schedule;
 
+use super::benchmark::{validate_all as validate_benchmarks, Defaults as BenchmarkDefaults};
 use super::Config;
 
 /// Context for a program invocation.
 pub struct ExecutionContext<'a> {
-    /// Command execution configuration
-    pub command: Command<Validated>,
+    /// Benchmarks to measure
+    pub benchmarks: Vec<Benchmark>,
     /// Command execution configuration
     pub build_command: Option<Command<Validated>>,
     /// Git context
     pub git_ctx: GitContext,
     /// Git references to compare
     pub git_targets: DiffTargets,
-    /// Template engine
-    template_engine: Formatter<'a>,
+    /// Order of base and head runs
+    pub schedule: Schedule,
+    /// Handling of outlier runs
+    pub outliers: Outliers,
+    /// Adaptive run count, if enabled
+    pub adaptive: Option<Adaptive>,
+    /// Pathspecs of the files affecting the benchmarks, empty for all files
+    pub paths: Vec<String>,
+    /// Results of identical trees, if enabled
+    pub cache: Option<Cache>,
+    /// Presentation of results
+    output: Output<'a>,
+    /// Subtree of the cgroups of measured runs, kept for the whole session
+    /// if running in cgroups
+    _cgroup_subtree: Option<Arc<CgroupSubtree>>,
 }
 
 impl ExecutionContext<'_> {
@@ -31,13 +59,60 @@ impl ExecutionContext<'_> {
         Self::try_from(config)
     }
 
-    /// Render program results to a string.
+    /// Render the results of a comparison to a string.
+    ///
+    /// # Errors
+    ///
+    /// Surfaces any errors encountered in the templating engine.
+    pub fn render_comparison(&self, comparison: &Comparison) -> Result<String> {
+        self.output.render_comparison(comparison)
+    }
+
+    /// Render the results of measuring a single reference to a string.
     ///
     /// # Errors
     ///
     /// Surfaces any errors encountered in the templating engine.
-    pub fn render_results(&self, results: Results) -> Result<String> {
-        self.template_engine.render_results(results)
+    pub fn render_run(&self, results: &RefResults) -> Result<String> {
+        self.output.render_run(results)
+    }
+
+    /// Check the custom metrics of a comparison against their thresholds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error listing every metric for which head is worse than base
+    /// by more than its threshold.
+    pub fn check_thresholds(&self, comparison: &Comparison) -> Result<()> {
+        let exceeded: Vec<String> = comparison
+            .benchmarks
+            .iter()
+            .flat_map(|result| {
+                self.benchmarks
+                    .iter()
+                    .filter(|benchmark| benchmark.name == result.name)
+                    .flat_map(|benchmark| &benchmark.metrics)
+                    .filter_map(|metric| {
+                        let base = result.base.aggregate.metrics.get(&metric.name)?;
+                        let head = result.head.aggregate.metrics.get(&metric.name)?;
+                        let worse = metric.exceeded_threshold(*base, *head)?;
+                        Some(format!(
+                            "`{}` of `{}` is {worse:.1}% worse, more than its threshold of {}%",
+                            metric.name,
+                            result.name,
+                            metric.threshold.unwrap_or_default(),
+                        ))
+                    })
+            })
+            .collect();
+        if exceeded.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Metrics exceeded their thresholds:\n{}",
+                exceeded.join("\n")
+            ))
+        }
     }
 }
 
@@ -47,6 +122,351 @@ fn missing_default_value(value_name: &str) -> impl Fn() -> anyhow::Error + use<'
     move || anyhow!("No value found for `{value_name}`. Ensure default values are initialized.")
 }
 
+/// Validate the build command, given either as a command or as a shell command string.
+///
+/// # Errors
+///
+/// Returns an error if both forms are given or the command fails to validate.
+fn validate_build_command(
+    config: &Config,
+    working_dir: &Path,
+    show_output: bool,
+    shell_program: &str,
+    check_shell_command: bool,
+) -> Result<Option<Command<Validated>>> {
+    let build_command = match (&config.build_command, &config.build_shell) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow!(
+                "Specify either a build command or a build shell command string, not both"
+            ))
+        }
+        (Some(build_command), None) => Command::new(
+            build_command.clone(),
+            config.build_arg.clone().unwrap_or_default(),
+            working_dir.to_path_buf(),
+            show_output,
+        ),
+        (None, Some(script)) => Command::shell(
+            shell_program.to_string(),
+            script.clone(),
+            working_dir.to_path_buf(),
+            show_output,
+        )
+        .with_script_check(check_shell_command),
+        (None, None) => return Ok(None),
+    };
+    build_command
+        .with_env(config.build_env.clone().unwrap_or_default())
+        .with_env_clear(
+            config.build_env_clear.unwrap_or_default(),
+            config.build_env_passthrough.clone().unwrap_or_default(),
+        )
+        .with_stdin_file(config.build_stdin_file.clone())
+        .validate()
+        .map(Some)
+        .map_err(|err| anyhow!("Build command: {err}"))
+}
+
+/// Create the output for the given template, or the terminal output if there is none.
+///
+/// # Errors
+///
+/// Returns an error if the template fails to validate
+/// against the metrics and parameters of the benchmarks.
+fn validate_output<'a>(
+    output_template: Option<String>,
+    benchmarks: &[Benchmark],
+) -> Result<Output<'a>> {
+    let metrics = || benchmarks.iter().flat_map(|benchmark| &benchmark.metrics);
+    let metric_names: Vec<&str> = metrics().map(|metric| metric.name.as_str()).collect();
+    let param_names: Vec<&str> = benchmarks
+        .iter()
+        .flat_map(|benchmark| benchmark.params.keys())
+        .map(String::as_str)
+        .collect();
+
+    let higher_is_better = metrics()
+        .filter(|metric| metric.higher_is_better)
+        .map(|metric| metric.name.clone());
+    Output::new(
+        output_template,
+        &metric_names,
+        &param_names,
+        higher_is_better,
+    )
+}
+
+/// Resolve the compared references, with base defaulting to the main branch.
+fn resolve_targets(
+    git_ctx: &GitContext,
+    base: Option<&str>,
+    head: Option<&str>,
+    main_branch_name: Option<&str>,
+) -> Result<DiffTargets> {
+    let default_branch = main_branch_name.ok_or_else(missing_default_value("main_branch_name"))?;
+    DiffTargets::from_string_refs(
+        git_ctx,
+        base.unwrap_or(default_branch),
+        head.unwrap_or("HEAD"),
+    )
+}
+
+/// Settings affecting the measured results, whose serialization identifies cached results.
+/// Settings applied after measuring, e.g. outlier rejection, are left out.
+#[derive(Serialize)]
+struct CacheSettings<'a> {
+    /// Version of `git-perfdiff`, which may measure differently.
+    version: &'a str,
+    /// Command to run.
+    command: Option<&'a String>,
+    /// Arguments to pass to program.
+    arg: Option<&'a Vec<String>>,
+    /// Command to run for build step.
+    build_command: Option<&'a String>,
+    /// Arguments to pass to build command.
+    build_arg: Option<&'a Vec<String>>,
+    /// Working directory for program execution.
+    working_dir: Option<&'a PathBuf>,
+    /// Custom metrics to extract from the program output.
+    metrics: Option<&'a Vec<MetricDefinition>>,
+    /// Named benchmarks to run.
+    benchmarks: Option<&'a Vec<BenchmarkDefinition>>,
+    /// Number of runs per benchmark and commit.
+    runs: Option<usize>,
+    /// Order of base and head runs.
+    schedule: Option<Schedule>,
+    /// CPUs to pin measured programs to.
+    cpus: Option<&'a Vec<usize>>,
+    /// Nice value of measured programs.
+    nice: Option<i32>,
+    /// Real-time `SCHED_FIFO` priority of measured programs.
+    fifo_priority: Option<i32>,
+    /// Environment variables set for measured programs.
+    env: Option<&'a BTreeMap<String, String>>,
+    /// Whether to start measured programs with an empty environment.
+    env_clear: Option<bool>,
+    /// Variables kept from the environment of measured programs.
+    env_passthrough: Option<&'a Vec<String>>,
+    /// File passed to measured programs on standard input.
+    stdin_file: Option<&'a PathBuf>,
+    /// Environment variables set for the build command.
+    build_env: Option<&'a BTreeMap<String, String>>,
+    /// Whether to start the build command with an empty environment.
+    build_env_clear: Option<bool>,
+    /// Variables kept from the environment of the build command.
+    build_env_passthrough: Option<&'a Vec<String>>,
+    /// File passed to the build command on standard input.
+    build_stdin_file: Option<&'a PathBuf>,
+    /// Shell command string to run.
+    shell: Option<&'a String>,
+    /// Shell command string for the build step.
+    build_shell: Option<&'a String>,
+    /// Shell executing command strings.
+    shell_program: Option<&'a String>,
+    /// Hook scripts run around the measurements.
+    hooks: HookScripts,
+    /// Whether to drop filesystem caches before every run.
+    cold: Option<bool>,
+    /// Shell command string dropping filesystem caches in cold mode.
+    drop_caches_command: Option<&'a String>,
+    /// Whether to collect performance counters of measured programs.
+    counters: Option<bool>,
+    /// Whether to run every measured program in a transient cgroup.
+    cgroup: Option<bool>,
+    /// Memory limit of measured programs.
+    memory_limit: Option<&'a String>,
+    /// CPU bandwidth limit of measured programs.
+    cpu_limit: Option<f64>,
+    /// Target confidence interval width of adaptive run counts.
+    target_ci_width: Option<f64>,
+    /// Most runs per benchmark and commit in adaptive mode.
+    max_runs: Option<usize>,
+    /// Seconds after which adaptive measuring of a benchmark stops.
+    time_budget: Option<f64>,
+}
+
+/// Settings affecting the measurements, identifying cached results, if caching is enabled.
+fn cache_fingerprint(config: &Config) -> Result<Option<String>> {
+    // Destructured exhaustively, so that every new setting is either cached or left out.
+    let Config {
+        command,
+        arg,
+        build_command,
+        build_arg,
+        working_dir,
+        show_output: _,
+        git_path: _,
+        base_git_ref: _,
+        head_git_ref: _,
+        main_branch_name: _,
+        output_template: _,
+        metrics,
+        benchmarks,
+        runs,
+        schedule,
+        cpus,
+        nice,
+        fifo_priority,
+        env,
+        env_clear,
+        env_passthrough,
+        stdin_file,
+        build_env,
+        build_env_clear,
+        build_env_passthrough,
+        build_stdin_file,
+        shell,
+        build_shell,
+        shell_program,
+        check_shell_command: _,
+        setup,
+        prepare,
+        cleanup,
+        teardown,
+        cold,
+        drop_caches_command,
+        counters,
+        cgroup,
+        memory_limit,
+        cpu_limit,
+        outliers: _,
+        outlier_threshold: _,
+        max_outlier_fraction: _,
+        target_ci_width,
+        max_runs,
+        time_budget,
+        paths: _,
+        cache,
+    } = config;
+    if *cache != Some(true) {
+        return Ok(None);
+    }
+    let settings = CacheSettings {
+        version: env!("CARGO_PKG_VERSION"),
+        command: command.as_ref(),
+        arg: arg.as_ref(),
+        build_command: build_command.as_ref(),
+        build_arg: build_arg.as_ref(),
+        working_dir: working_dir.as_ref(),
+        metrics: metrics.as_ref(),
+        benchmarks: benchmarks.as_ref(),
+        runs: *runs,
+        schedule: *schedule,
+        cpus: cpus.as_ref(),
+        nice: *nice,
+        fifo_priority: *fifo_priority,
+        env: env.as_ref(),
+        env_clear: *env_clear,
+        env_passthrough: env_passthrough.as_ref(),
+        stdin_file: stdin_file.as_ref(),
+        build_env: build_env.as_ref(),
+        build_env_clear: *build_env_clear,
+        build_env_passthrough: build_env_passthrough.as_ref(),
+        build_stdin_file: build_stdin_file.as_ref(),
+        shell: shell.as_ref(),
+        build_shell: build_shell.as_ref(),
+        shell_program: shell_program.as_ref(),
+        hooks: HookScripts {
+            setup: setup.clone(),
+            prepare: prepare.clone(),
+            cleanup: cleanup.clone(),
+            teardown: teardown.clone(),
+        },
+        cold: *cold,
+        drop_caches_command: drop_caches_command.as_ref(),
+        counters: *counters,
+        cgroup: *cgroup,
+        memory_limit: memory_limit.as_ref(),
+        cpu_limit: *cpu_limit,
+        target_ci_width: *target_ci_width,
+        max_runs: *max_runs,
+        time_budget: *time_budget,
+    };
+    Ok(Some(serde_json::to_string(&settings)?))
+}
+
+/// Limits of the transient cgroups of measured runs, if running in cgroups.
+/// Setting a limit implies running in cgroups.
+fn cgroup_limits(config: &Config) -> Option<CgroupLimits> {
+    let limits = CgroupLimits {
+        memory: config.memory_limit.clone(),
+        cpus: config.cpu_limit,
+    };
+    (config.cgroup == Some(true) || limits != CgroupLimits::default()).then_some(limits)
+}
+
+/// Outlier handling with the method's default threshold, if none is configured.
+fn validate_outliers(config: &Config) -> Result<Outliers> {
+    let method = config
+        .outliers
+        .ok_or_else(missing_default_value("outliers"))?;
+    let threshold = config
+        .outlier_threshold
+        .unwrap_or_else(|| method.default_threshold());
+    let max_fraction = config
+        .max_outlier_fraction
+        .ok_or_else(missing_default_value("max_outlier_fraction"))?;
+    if !(threshold.is_finite() && threshold >= 0.0) {
+        return Err(anyhow!("Outlier threshold must be a non-negative number"));
+    }
+    if !(0.0..=1.0).contains(&max_fraction) {
+        return Err(anyhow!("Maximum outlier fraction must be between 0 and 1"));
+    }
+    Ok(Outliers {
+        method,
+        threshold,
+        max_fraction,
+    })
+}
+
+/// Adaptive run count settings, if a target confidence interval width is set.
+fn validate_adaptive(config: &Config) -> Result<Option<Adaptive>> {
+    let Some(target_width) = config.target_ci_width else {
+        return Ok(None);
+    };
+    if !(target_width.is_finite() && target_width > 0.0) {
+        return Err(anyhow!("Target confidence interval width must be positive"));
+    }
+    if !config.schedule.is_some_and(Schedule::is_interleaved) {
+        return Err(anyhow!(
+            "Adaptive run counts require an interleaved schedule, `alternate` or `random`"
+        ));
+    }
+    let time_budget = config
+        .time_budget
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .ok()
+        .filter(|budget| budget.is_none_or(|budget| !budget.is_zero()))
+        .ok_or_else(|| anyhow!("Time budget must be a positive number of seconds"))?;
+    let max_runs = config
+        .max_runs
+        .ok_or_else(missing_default_value("max_runs"))?;
+    // A confidence interval needs at least two runs per side.
+    let min_runs = config.runs.unwrap_or_default().max(2);
+    if max_runs < min_runs {
+        return Err(anyhow!("Maximum run count must be at least {min_runs}"));
+    }
+    Ok(Some(Adaptive {
+        target_width,
+        max_runs,
+        time_budget,
+    }))
+}
+
+/// The command dropping caches before each run, if running cold.
+fn drop_caches_command(config: &Config) -> Result<Option<String>> {
+    let cold = config.cold.ok_or_else(missing_default_value("cold"))?;
+    if !cold {
+        return Ok(None);
+    }
+    config
+        .drop_caches_command
+        .clone()
+        .map(Some)
+        .ok_or_else(missing_default_value("drop_caches_command"))
+}
+
 impl TryFrom<Config> for ExecutionContext<'_> {
     type Error = anyhow::Error;
 
@@ -57,59 +477,147 @@ impl TryFrom<Config> for ExecutionContext<'_> {
             .ok_or_else(missing_default_value("show_output"))?;
         let git_path = config
             .git_path
+            .clone()
             .ok_or_else(missing_default_value("git_path"))?;
-        let command = config
-            .command
-            .ok_or_else(|| anyhow!("No command to run!"))?;
-
-        let working_dir = config.working_dir.unwrap_or_else(|| git_path.clone());
-
-        let build_command = config
-            .build_command
-            .map(|build_command| {
-                Command::new(
-                    build_command,
-                    config.build_arg.unwrap_or_default(),
-                    working_dir.clone(),
-                    show_output,
-                )
-                .validate()
-            })
-            .transpose()?;
+        let working_dir = config
+            .working_dir
+            .clone()
+            .unwrap_or_else(|| git_path.clone());
 
-        let command = Command::new(
-            command,
-            config.arg.unwrap_or_default(),
-            working_dir,
+        let shell_program = config
+            .shell_program
+            .clone()
+            .ok_or_else(missing_default_value("shell_program"))?;
+        let check_shell_command = config
+            .check_shell_command
+            .ok_or_else(missing_default_value("check_shell_command"))?;
+
+        let build_command = validate_build_command(
+            &config,
+            &working_dir,
             show_output,
-        )
-        .validate()?;
+            &shell_program,
+            check_shell_command,
+        )?;
+
+        let cache_fingerprint = cache_fingerprint(&config)?;
+        let drop_caches = drop_caches_command(&config)?;
+        let cgroup = cgroup_limits(&config);
+        let outliers = validate_outliers(&config)?;
+        let adaptive = validate_adaptive(&config)?;
+        let runs = config.runs.ok_or_else(missing_default_value("runs"))?;
+        let schedule = config
+            .schedule
+            .ok_or_else(missing_default_value("schedule"))?;
+        let metrics = config.metrics.unwrap_or_default();
+        // A command given directly replaces the configured benchmarks.
+        let definitions = match (config.command, config.shell, config.benchmarks) {
+            (Some(command), _, _) => vec![BenchmarkDefinition::from_command(
+                command,
+                config.arg.unwrap_or_default(),
+            )],
+            (None, Some(script), _) => vec![BenchmarkDefinition::from_shell(script)],
+            (None, None, Some(benchmarks)) if !benchmarks.is_empty() => benchmarks,
+            _ => return Err(anyhow!("No command to run!")),
+        };
+        let benchmarks = validate_benchmarks(
+            definitions,
+            &BenchmarkDefaults {
+                working_dir: &working_dir,
+                show_output,
+                runs,
+                metrics: &metrics,
+                env: &config.env.unwrap_or_default(),
+                env_clear: config.env_clear.unwrap_or_default(),
+                env_passthrough: &config.env_passthrough.unwrap_or_default(),
+                stdin_file: config.stdin_file.as_deref(),
+                shell_program: &shell_program,
+                check_shell_command,
+                hooks: &HookScripts {
+                    setup: config.setup,
+                    prepare: config.prepare,
+                    cleanup: config.cleanup,
+                    teardown: config.teardown,
+                },
+                drop_caches: drop_caches.as_deref(),
+                counters: config
+                    .counters
+                    .ok_or_else(missing_default_value("counters"))?,
+                cgroup: cgroup.as_ref(),
+                scheduling: &Scheduling {
+                    cpus: config.cpus,
+                    nice: config.nice,
+                    fifo_priority: config.fifo_priority,
+                },
+            },
+        )?;
 
         let git_ctx = GitContext::try_from(git_path)?;
+        // Another run may have checked out a different commit, changing what refs resolve to.
+        git_ctx.lock()?;
+        let cgroup_subtree = cgroup.is_some().then(CgroupSubtree::shared).transpose()?;
 
-        let default_branch = config
-            .main_branch_name
-            .ok_or_else(missing_default_value("main_branch_name"))?;
-        let git_targets = DiffTargets::from_string_refs(
+        let git_targets = resolve_targets(
             &git_ctx,
-            config
-                .base_git_ref
-                .as_ref()
-                .map_or(default_branch.as_str(), |v| v),
-            config.head_git_ref.as_ref().map_or("HEAD", |v| v),
+            config.base_git_ref.as_deref(),
+            config.head_git_ref.as_deref(),
+            config.main_branch_name.as_deref(),
         )?;
 
-        let output_template = config
-            .output_template
-            .ok_or_else(missing_default_value("output_template"))?;
-        let template_engine = Formatter::from_template_string(output_template)?;
+        let output = validate_output(config.output_template, &benchmarks)?;
 
         Ok(Self {
-            command,
+            benchmarks,
             build_command,
-            git_ctx,
             git_targets,
-            template_engine,
+            schedule,
+            outliers,
+            adaptive,
+            paths: config.paths.unwrap_or_default(),
+            cache: cache_fingerprint
+                .map(|fingerprint| Cache::new(git_ctx.scratch_dir().join("cache"), fingerprint)),
+            git_ctx,
+            output,
+            _cgroup_subtree: cgroup_subtree,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{cache_fingerprint, Config};
+
+    #[test]
+    fn fingerprint_of_measurement_settings() {
+        let config = Config {
+            command: Some("true".to_string()),
+            cache: Some(true),
+            ..Config::default()
+        };
+        let fingerprint = cache_fingerprint(&config).unwrap();
+        assert!(fingerprint.is_some());
+        assert_eq!(
+            cache_fingerprint(&Config {
+                cache: Some(false),
+                ..config.clone()
+            })
+            .unwrap(),
+            None
+        );
+
+        // Settings applied after measuring leave the fingerprint unchanged.
+        let shown = Config {
+            output_template: Some("{{ head }}".to_string()),
+            max_outlier_fraction: Some(0.5),
+            head_git_ref: Some("v1".to_string()),
+            ..config.clone()
+        };
+        assert_eq!(cache_fingerprint(&shown).unwrap(), fingerprint);
+
+        let measured = Config {
+            runs: Some(5),
+            ..config
+        };
+        assert_ne!(cache_fingerprint(&measured).unwrap(), fingerprint);
+    }
+}
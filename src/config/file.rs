@@ -1,7 +1,15 @@
+use super::BenchmarkDefinition;
 use super::Config;
+use crate::metrics::Definition as MetricDefinition;
+use crate::outliers::Method as OutlierMethod;
+use crate::schedule::Schedule;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+/// Path of the config file, relative to the current directory.
+pub const CONFIG_FILE: &str = ".perfdiff.toml";
+
 /// Contains all options that can be set in the config file
 #[derive(Deserialize, Default)]
 struct ConfigFile {
@@ -12,8 +20,119 @@ struct ConfigFile {
     /// Default is "main"
     main_branch_name: Option<String>,
     /// Template for program output.
-    /// Default is each measurement on it's own line
+    /// Default is the built-in terminal comparison table
     output_template: Option<String>,
+    /// Custom metrics to extract from the output of every benchmark.
+    /// Each metric is a `[[metric]]` table.
+    /// A benchmark adds its own in `[[benchmark.metric]]` tables.
+    metric: Option<Vec<MetricDefinition>>,
+    /// Named benchmarks, each a `[[benchmark]]` table.
+    /// Ignored if a command is given on the command line.
+    benchmark: Option<Vec<BenchmarkDefinition>>,
+    /// Number of runs per benchmark and commit.
+    /// Default is 1
+    runs: Option<usize>,
+    /// Order of base and head runs: "sequential", "alternate" or "random".
+    /// Default is "sequential"
+    schedule: Option<Schedule>,
+    /// CPUs to pin measured programs to, e.g. `[2, 3]`.
+    /// Default is all CPUs
+    cpus: Option<Vec<usize>>,
+    /// Nice value of measured programs, from -20 to 19.
+    nice: Option<i32>,
+    /// Real-time `SCHED_FIFO` priority of measured programs, from 1 to 99.
+    fifo_priority: Option<i32>,
+    /// Environment variables set for measured programs, e.g. `{ RUST_LOG = "info" }`.
+    /// Benchmarks may add or override variables
+    env: Option<BTreeMap<String, String>>,
+    /// Whether to start measured programs with an empty environment.
+    /// Default is false
+    env_clear: Option<bool>,
+    /// Variables kept from the environment of measured programs
+    /// when it is cleared, e.g. `["PATH", "HOME"]`
+    env_passthrough: Option<Vec<String>>,
+    /// File passed to measured programs on standard input,
+    /// relative to the working directory
+    stdin_file: Option<PathBuf>,
+    /// Environment variables set for the build command
+    build_env: Option<BTreeMap<String, String>>,
+    /// Whether to start the build command with an empty environment.
+    /// Default is false
+    build_env_clear: Option<bool>,
+    /// Variables kept from the environment of the build command
+    /// when it is cleared
+    build_env_passthrough: Option<Vec<String>>,
+    /// File passed to the build command on standard input,
+    /// relative to the working directory
+    build_stdin_file: Option<PathBuf>,
+    /// Shell command string to run, e.g. `"./prepare.sh && ./bench"`.
+    /// Alternative to `[[benchmark]]` tables
+    shell: Option<String>,
+    /// Shell command string for the build step, e.g. `"cargo build --release"`.
+    /// Measuring fails if it exits with a non-zero status
+    build_shell: Option<String>,
+    /// Shell executing command strings as `<shell_program> -c <string>`.
+    /// Default is "sh"
+    shell_program: Option<String>,
+    /// Whether to check that the first word of a command string is
+    /// an existing program. Default is false
+    check_shell_command: Option<bool>,
+    /// Shell command string run once per commit, before the first run.
+    /// Benchmarks may override all hooks
+    setup: Option<String>,
+    /// Shell command string run before every run, e.g. to reset a database.
+    /// Its run time is not measured
+    prepare: Option<String>,
+    /// Shell command string run after every run. Its run time is not measured
+    cleanup: Option<String>,
+    /// Shell command string run once per commit, after the last run
+    teardown: Option<String>,
+    /// Whether to drop filesystem caches before every run, to measure cold starts.
+    /// Default is false
+    cold: Option<bool>,
+    /// Shell command string dropping filesystem caches in cold mode.
+    /// Default is `sync && echo 3 > /proc/sys/vm/drop_caches`, which requires root
+    drop_caches_command: Option<String>,
+    /// Whether to collect performance counters of measured programs, such as
+    /// instructions and cache misses. Linux only. Default is false
+    counters: Option<bool>,
+    /// Whether to run every measured program in a transient cgroup v2,
+    /// reporting statistics of its entire process tree. Linux only. Default is false
+    cgroup: Option<bool>,
+    /// Memory limit of measured programs, in bytes or with a `K`, `M` or `G` suffix.
+    /// Implies `cgroup`
+    memory_limit: Option<String>,
+    /// CPU bandwidth limit of measured programs in CPUs, e.g. `1.5`. Implies `cgroup`
+    cpu_limit: Option<f64>,
+    /// How to detect outlier runs in wall time and custom metrics,
+    /// `none`, `iqr` or `mad`. Outliers are excluded from the comparison.
+    /// Default is none
+    outliers: Option<OutlierMethod>,
+    /// Threshold of the outlier detection method: the IQR fence factor
+    /// or the maximum modified z-score. Default is 1.5 for `iqr` and 3.5 for `mad`
+    outlier_threshold: Option<f64>,
+    /// Fraction of rejected runs above which a warning is shown. Default is 0.1
+    max_outlier_fraction: Option<f64>,
+    /// Width in percentage points of the 95% confidence interval of the relative
+    /// wall time difference at which measuring stops. Enables adaptive run counts,
+    /// with `runs` as the minimum. Requires an interleaved schedule
+    target_ci_width: Option<f64>,
+    /// Most runs per benchmark and commit in adaptive mode, at least `runs` and 2.
+    /// Default is 100
+    max_runs: Option<usize>,
+    /// Seconds after which adaptive measuring of a benchmark stops, greater than 0.
+    /// Default is no limit
+    time_budget: Option<f64>,
+    /// Pathspecs of the files affecting the benchmarks, e.g. `["src", "Cargo.*"]`.
+    /// If none of them changed between base and head, head is not measured
+    /// and reuses the results of base. Default is all files
+    paths: Option<Vec<String>>,
+    /// Whether to reuse results measured on a commit with an identical tree
+    /// and the same settings, e.g. before a rebase. Results are cached
+    /// in the `perfdiff/cache` directory of the git directory.
+    /// With interleaved schedules, a side with cached results is neither built nor run,
+    /// and only the other side is measured. Default is false
+    cache: Option<bool>,
 }
 
 impl From<ConfigFile> for Config {
@@ -22,11 +141,85 @@ impl From<ConfigFile> for Config {
             working_dir,
             main_branch_name,
             output_template,
+            metric,
+            benchmark,
+            runs,
+            schedule,
+            cpus,
+            nice,
+            fifo_priority,
+            env,
+            env_clear,
+            env_passthrough,
+            stdin_file,
+            build_env,
+            build_env_clear,
+            build_env_passthrough,
+            build_stdin_file,
+            shell,
+            build_shell,
+            shell_program,
+            check_shell_command,
+            setup,
+            prepare,
+            cleanup,
+            teardown,
+            cold,
+            drop_caches_command,
+            counters,
+            cgroup,
+            memory_limit,
+            cpu_limit,
+            outliers,
+            outlier_threshold,
+            max_outlier_fraction,
+            target_ci_width,
+            max_runs,
+            time_budget,
+            paths,
+            cache,
         } = config_file;
         Self {
             working_dir,
             main_branch_name,
             output_template,
+            metrics: metric,
+            benchmarks: benchmark,
+            runs,
+            schedule,
+            cpus,
+            nice,
+            fifo_priority,
+            env,
+            env_clear,
+            env_passthrough,
+            stdin_file,
+            build_env,
+            build_env_clear,
+            build_env_passthrough,
+            build_stdin_file,
+            shell,
+            build_shell,
+            shell_program,
+            check_shell_command,
+            setup,
+            prepare,
+            cleanup,
+            teardown,
+            cold,
+            drop_caches_command,
+            counters,
+            cgroup,
+            memory_limit,
+            cpu_limit,
+            outliers,
+            outlier_threshold,
+            max_outlier_fraction,
+            target_ci_width,
+            max_runs,
+            time_budget,
+            paths,
+            cache,
             ..Self::empty()
         }
     }
@@ -42,3 +235,32 @@ pub fn load(file_path: impl AsRef<Path>) -> Config {
         )
         .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigFile;
+    use crate::cli::Args;
+    use crate::config::Config;
+    use clap::Parser;
+
+    #[test]
+    fn command_forms_by_layer() {
+        let file: Config =
+            toml::from_str::<ConfigFile>("build_shell = \"make all\"\nshell = \"./bench --fast\"")
+                .unwrap()
+                .into();
+        let args = |args: &[&str]| -> Config { Args::try_parse_from(args).unwrap().into() };
+
+        // The command line replaces either form given in the file.
+        let config =
+            args(&["git-perfdiff", "-B", "make", "-c", "./bench"]).extend_with(file.clone());
+        assert_eq!(config.build_command.as_deref(), Some("make"));
+        assert_eq!(config.build_shell, None);
+        assert_eq!(config.command.as_deref(), Some("./bench"));
+        assert_eq!(config.shell, None);
+
+        let config = args(&["git-perfdiff"]).extend_with(file);
+        assert_eq!(config.build_shell.as_deref(), Some("make all"));
+        assert_eq!(config.shell.as_deref(), Some("./bench --fast"));
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::{Command, Validated};
+use crate::signals;
+
+/// Command dropping the page cache, dentries and inodes. Requires root privileges.
+pub const DEFAULT_DROP_CACHES: &str = "sync && echo 3 > /proc/sys/vm/drop_caches";
+
+/// Shell command strings run around the measurements of a benchmark.
+/// Their run time is not measured.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Scripts {
+    /// Run once per commit, before the first run.
+    pub setup: Option<String>,
+    /// Run before every run.
+    pub prepare: Option<String>,
+    /// Run after every run.
+    pub cleanup: Option<String>,
+    /// Run once per commit, after the last run.
+    pub teardown: Option<String>,
+}
+
+impl Scripts {
+    /// Fill in unset scripts from the defaults.
+    #[must_use]
+    pub(super) fn or(self, defaults: &Self) -> Self {
+        Self {
+            setup: self.setup.or_else(|| defaults.setup.clone()),
+            prepare: self.prepare.or_else(|| defaults.prepare.clone()),
+            cleanup: self.cleanup.or_else(|| defaults.cleanup.clone()),
+            teardown: self.teardown.or_else(|| defaults.teardown.clone()),
+        }
+    }
+
+    /// Transform every configured script, e.g. to fill in template variables.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error returned by `transform`.
+    pub(super) fn try_map(&self, transform: impl Fn(&String) -> Result<String>) -> Result<Self> {
+        let map = |script: &Option<String>| script.as_ref().map(&transform).transpose();
+        Ok(Self {
+            setup: map(&self.setup)?,
+            prepare: map(&self.prepare)?,
+            cleanup: map(&self.cleanup)?,
+            teardown: map(&self.teardown)?,
+        })
+    }
+}
+
+/// Point in the measurement of a benchmark at which a hook runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    /// Once per commit, before the first run.
+    Setup,
+    /// Before every run.
+    Prepare,
+    /// After every run.
+    Cleanup,
+    /// Once per commit, after the last run.
+    Teardown,
+    /// Before every run in cold mode, after `Prepare`.
+    DropCaches,
+}
+
+impl Hook {
+    /// Name of the hook as used in the configuration.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Setup => "setup",
+            Self::Prepare => "prepare",
+            Self::Cleanup => "cleanup",
+            Self::Teardown => "teardown",
+            Self::DropCaches => "drop_caches",
+        }
+    }
+
+    /// Run a command of this hook.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be started or does not succeed.
+    pub fn run(self, command: &Command<Validated>) -> Result<()> {
+        let status = signals::status(&mut command.to_command()?)?;
+        if !status.success() {
+            return Err(anyhow!("`{}` hook failed with {status}", self.name()));
+        }
+        Ok(())
+    }
+}
+
+/// Validated hook commands of a benchmark.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    /// Command run once per commit, before the first run.
+    pub setup: Option<Command<Validated>>,
+    /// Command run before every run.
+    pub prepare: Option<Command<Validated>>,
+    /// Command run after every run.
+    pub cleanup: Option<Command<Validated>>,
+    /// Command run once per commit, after the last run.
+    pub teardown: Option<Command<Validated>>,
+    /// Command dropping filesystem caches before every run, in cold mode.
+    pub drop_caches: Option<Command<Validated>>,
+}
+
+impl Hooks {
+    /// Validate hook scripts and the cache drop command, running them with `shell`
+    /// in the working directory and environment of the benchmark command.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the hook commands fails to validate.
+    pub(super) fn validate(
+        scripts: Scripts,
+        drop_caches: Option<&str>,
+        benchmark: &Command<Validated>,
+        shell: &str,
+        check_shell_command: bool,
+    ) -> Result<Self> {
+        let validate = |hook: Hook, script: Option<String>| {
+            script
+                .map(|script| {
+                    Command::shell(
+                        shell.to_string(),
+                        script,
+                        benchmark.working_dir.clone(),
+                        benchmark.show_output,
+                    )
+                    .with_script_check(check_shell_command)
+                    .with_env(benchmark.env.clone())
+                    .with_env_clear(benchmark.env_clear, benchmark.env_passthrough.clone())
+                    .validate()
+                    .map_err(|err| anyhow!("`{}` hook: {err}", hook.name()))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            setup: validate(Hook::Setup, scripts.setup)?,
+            prepare: validate(Hook::Prepare, scripts.prepare)?,
+            cleanup: validate(Hook::Cleanup, scripts.cleanup)?,
+            teardown: validate(Hook::Teardown, scripts.teardown)?,
+            drop_caches: validate(Hook::DropCaches, drop_caches.map(str::to_string))?,
+        })
+    }
+
+    /// Command of a hook, if configured.
+    #[must_use]
+    pub const fn get(&self, hook: Hook) -> Option<&Command<Validated>> {
+        match hook {
+            Hook::Setup => self.setup.as_ref(),
+            Hook::Prepare => self.prepare.as_ref(),
+            Hook::Cleanup => self.cleanup.as_ref(),
+            Hook::Teardown => self.teardown.as_ref(),
+            Hook::DropCaches => self.drop_caches.as_ref(),
+        }
+    }
+
+    /// Run a hook if it is configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hook cannot be started or does not succeed.
+    pub fn run(&self, hook: Hook) -> Result<()> {
+        self.get(hook).map_or(Ok(()), |command| hook.run(command))
+    }
+
+    /// Copy of the hooks with their working directories relocated,
+    /// see [`Command::relocate`].
+    #[must_use]
+    pub fn relocate(&self, from: &Path, to: &Path) -> Self {
+        let relocate = |command: &Option<Command<Validated>>| {
+            command.as_ref().map(|command| command.relocate(from, to))
+        };
+        Self {
+            setup: relocate(&self.setup),
+            prepare: relocate(&self.prepare),
+            cleanup: relocate(&self.cleanup),
+            teardown: relocate(&self.teardown),
+            drop_caches: relocate(&self.drop_caches),
+        }
+    }
+}
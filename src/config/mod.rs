@@ -1,19 +1,37 @@
+use std::collections::BTreeMap;
 use std::env::current_dir;
 use std::path::PathBuf;
 
+use crate::adaptive::DEFAULT_MAX_RUNS;
+use crate::metrics::Definition as MetricDefinition;
+use crate::outliers::Method as OutlierMethod;
+use crate::schedule::Schedule;
+
+/// Fraction of rejected outlier runs above which a warning is shown by default.
+const DEFAULT_MAX_OUTLIER_FRACTION: f64 = 0.1;
+
+/// Named benchmarks.
+mod benchmark;
+pub use benchmark::{Benchmark, Definition as BenchmarkDefinition};
+
 /// Configuration for command execution.
 mod command;
 
+/// Commands run around measurements.
+mod hooks;
+pub use hooks::{Hook, Hooks, Scripts as HookScripts, DEFAULT_DROP_CACHES};
+
 pub use command::Config as Command;
 pub use command::Validated;
+pub use command::{Scheduling, DEFAULT_SHELL, PERFORMANCE_GOVERNOR};
 
 /// Configuration for output formatting.
 mod output;
-pub use output::Formatter;
+pub use output::{Formatter, Output, Terminal};
 
 /// Configuration loaded from file.
 mod file;
-pub use file::load as load_config_file;
+pub use file::{load as load_config_file, CONFIG_FILE};
 
 /// Configuration from CLI.
 mod cli;
@@ -38,8 +56,21 @@ fn get_current_dir() -> Option<PathBuf> {
     )
 }
 
+/// The alternative forms of a command given by `own`, if it gives either,
+/// and otherwise those given by `other`.
+fn either_form<A, B>(
+    own: (Option<A>, Option<B>),
+    other: (Option<A>, Option<B>),
+) -> (Option<A>, Option<B>) {
+    if own.0.is_some() || own.1.is_some() {
+        own
+    } else {
+        other
+    }
+}
+
 /// Full configuration.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Config {
     /// Command to run
     pub command: Option<String>,
@@ -73,8 +104,135 @@ pub struct Config {
     pub main_branch_name: Option<String>,
 
     /// Template for program output.
-    /// Default is each measurement on it's own line
+    /// Default is the built-in terminal comparison table
     pub output_template: Option<String>,
+
+    /// Custom metrics to extract from the program output
+    pub metrics: Option<Vec<MetricDefinition>>,
+
+    /// Named benchmarks to run, instead of a single command
+    pub benchmarks: Option<Vec<BenchmarkDefinition>>,
+
+    /// Number of runs per benchmark and commit.
+    /// Default is 1
+    pub runs: Option<usize>,
+
+    /// Order of base and head runs.
+    /// Default is sequential
+    pub schedule: Option<Schedule>,
+
+    /// CPUs to pin measured programs to
+    pub cpus: Option<Vec<usize>>,
+
+    /// Nice value of measured programs
+    pub nice: Option<i32>,
+
+    /// Real-time `SCHED_FIFO` priority of measured programs
+    pub fifo_priority: Option<i32>,
+
+    /// Environment variables set for measured programs
+    pub env: Option<BTreeMap<String, String>>,
+
+    /// Whether to start measured programs with an empty environment
+    pub env_clear: Option<bool>,
+
+    /// Variables kept from the environment of measured programs
+    /// when it is cleared
+    pub env_passthrough: Option<Vec<String>>,
+
+    /// File passed to measured programs on standard input
+    pub stdin_file: Option<PathBuf>,
+
+    /// Environment variables set for the build command
+    pub build_env: Option<BTreeMap<String, String>>,
+
+    /// Whether to start the build command with an empty environment
+    pub build_env_clear: Option<bool>,
+
+    /// Variables kept from the environment of the build command
+    /// when it is cleared
+    pub build_env_passthrough: Option<Vec<String>>,
+
+    /// File passed to the build command on standard input
+    pub build_stdin_file: Option<PathBuf>,
+
+    /// Shell command string to run, instead of a command and arguments
+    pub shell: Option<String>,
+
+    /// Shell command string for the build step
+    pub build_shell: Option<String>,
+
+    /// Shell executing command strings.
+    /// Default is "sh"
+    pub shell_program: Option<String>,
+
+    /// Whether to check that the first word of a command string is
+    /// an existing program. Default is false
+    pub check_shell_command: Option<bool>,
+
+    /// Shell command string run once per commit, before the first run
+    pub setup: Option<String>,
+
+    /// Shell command string run before every run, not measured
+    pub prepare: Option<String>,
+
+    /// Shell command string run after every run, not measured
+    pub cleanup: Option<String>,
+
+    /// Shell command string run once per commit, after the last run
+    pub teardown: Option<String>,
+
+    /// Whether to drop filesystem caches before every run.
+    /// Default is false
+    pub cold: Option<bool>,
+
+    /// Shell command string dropping filesystem caches in cold mode
+    pub drop_caches_command: Option<String>,
+
+    /// Whether to collect performance counters of measured programs.
+    /// Default is false
+    pub counters: Option<bool>,
+
+    /// Whether to run every measured program in a transient cgroup v2.
+    /// Default is false
+    pub cgroup: Option<bool>,
+
+    /// Memory limit of measured programs, e.g. `512M`. Implies `cgroup`
+    pub memory_limit: Option<String>,
+
+    /// CPU bandwidth limit of measured programs in CPUs, e.g. `1.5`. Implies `cgroup`
+    pub cpu_limit: Option<f64>,
+
+    /// How to detect outlier runs, which are excluded from the comparison.
+    /// Default is none
+    pub outliers: Option<OutlierMethod>,
+
+    /// Threshold of the outlier detection method.
+    /// Default depends on the method
+    pub outlier_threshold: Option<f64>,
+
+    /// Fraction of rejected runs above which a warning is shown.
+    /// Default is 0.1
+    pub max_outlier_fraction: Option<f64>,
+
+    /// Width in percentage points of the confidence interval of the wall time
+    /// difference at which adaptive measuring stops. Enables adaptive run counts
+    pub target_ci_width: Option<f64>,
+
+    /// Most runs per benchmark and commit in adaptive mode.
+    /// Default is 100
+    pub max_runs: Option<usize>,
+
+    /// Seconds after which adaptive measuring of a benchmark stops
+    pub time_budget: Option<f64>,
+
+    /// Pathspecs of the files affecting the benchmarks.
+    /// Head reuses the results of base if none of them changed
+    pub paths: Option<Vec<String>>,
+
+    /// Whether to reuse results measured on an identical tree with the same settings.
+    /// Default is false
+    pub cache: Option<bool>,
 }
 
 impl Config {
@@ -82,10 +240,18 @@ impl Config {
     /// those from another config object.
     #[must_use]
     pub fn extend_with(self, other: Self) -> Self {
+        // A command and a shell command string replace each other, so the forms are
+        // taken together from the first layer giving either.
+        let (command, shell) =
+            either_form((self.command, self.shell), (other.command, other.shell));
+        let (build_command, build_shell) = either_form(
+            (self.build_command, self.build_shell),
+            (other.build_command, other.build_shell),
+        );
         Self {
-            command: self.command.or(other.command),
+            command,
             arg: self.arg.or(other.arg),
-            build_command: self.build_command.or(other.build_command),
+            build_command,
             build_arg: self.build_arg.or(other.build_arg),
             working_dir: self.working_dir.or(other.working_dir),
             show_output: self.show_output.or(other.show_output),
@@ -94,6 +260,43 @@ impl Config {
             head_git_ref: self.head_git_ref.or(other.head_git_ref),
             main_branch_name: self.main_branch_name.or(other.main_branch_name),
             output_template: self.output_template.or(other.output_template),
+            metrics: self.metrics.or(other.metrics),
+            benchmarks: self.benchmarks.or(other.benchmarks),
+            runs: self.runs.or(other.runs),
+            schedule: self.schedule.or(other.schedule),
+            cpus: self.cpus.or(other.cpus),
+            nice: self.nice.or(other.nice),
+            fifo_priority: self.fifo_priority.or(other.fifo_priority),
+            env: self.env.or(other.env),
+            env_clear: self.env_clear.or(other.env_clear),
+            env_passthrough: self.env_passthrough.or(other.env_passthrough),
+            stdin_file: self.stdin_file.or(other.stdin_file),
+            build_env: self.build_env.or(other.build_env),
+            build_env_clear: self.build_env_clear.or(other.build_env_clear),
+            build_env_passthrough: self.build_env_passthrough.or(other.build_env_passthrough),
+            build_stdin_file: self.build_stdin_file.or(other.build_stdin_file),
+            shell,
+            build_shell,
+            shell_program: self.shell_program.or(other.shell_program),
+            check_shell_command: self.check_shell_command.or(other.check_shell_command),
+            setup: self.setup.or(other.setup),
+            prepare: self.prepare.or(other.prepare),
+            cleanup: self.cleanup.or(other.cleanup),
+            teardown: self.teardown.or(other.teardown),
+            cold: self.cold.or(other.cold),
+            drop_caches_command: self.drop_caches_command.or(other.drop_caches_command),
+            counters: self.counters.or(other.counters),
+            cgroup: self.cgroup.or(other.cgroup),
+            memory_limit: self.memory_limit.or(other.memory_limit),
+            cpu_limit: self.cpu_limit.or(other.cpu_limit),
+            outliers: self.outliers.or(other.outliers),
+            outlier_threshold: self.outlier_threshold.or(other.outlier_threshold),
+            max_outlier_fraction: self.max_outlier_fraction.or(other.max_outlier_fraction),
+            target_ci_width: self.target_ci_width.or(other.target_ci_width),
+            max_runs: self.max_runs.or(other.max_runs),
+            time_budget: self.time_budget.or(other.time_budget),
+            paths: self.paths.or(other.paths),
+            cache: self.cache.or(other.cache),
         }
     }
 
@@ -118,6 +321,43 @@ impl Config {
             head_git_ref: None,
             main_branch_name: None,
             output_template: None,
+            metrics: None,
+            benchmarks: None,
+            runs: None,
+            schedule: None,
+            cpus: None,
+            nice: None,
+            fifo_priority: None,
+            env: None,
+            env_clear: None,
+            env_passthrough: None,
+            stdin_file: None,
+            build_env: None,
+            build_env_clear: None,
+            build_env_passthrough: None,
+            build_stdin_file: None,
+            shell: None,
+            build_shell: None,
+            shell_program: None,
+            check_shell_command: None,
+            setup: None,
+            prepare: None,
+            cleanup: None,
+            teardown: None,
+            cold: None,
+            drop_caches_command: None,
+            counters: None,
+            cgroup: None,
+            memory_limit: None,
+            cpu_limit: None,
+            outliers: None,
+            outlier_threshold: None,
+            max_outlier_fraction: None,
+            target_ci_width: None,
+            max_runs: None,
+            time_budget: None,
+            paths: None,
+            cache: None,
         }
     }
 }
@@ -132,9 +372,18 @@ impl Default for Config {
             git_path: get_current_dir(),
             head_git_ref: Some("HEAD".to_string()),
             main_branch_name: Some("main".to_string()),
-            output_template: Some(
-                "Ran in {{ wall_time.secs + wall_time.nanos / 1e9 }} s.".to_string(),
-            ),
+            runs: Some(1),
+            schedule: Some(Schedule::default()),
+            shell_program: Some(DEFAULT_SHELL.to_string()),
+            check_shell_command: Some(false),
+            cold: Some(false),
+            counters: Some(false),
+            cgroup: Some(false),
+            cache: Some(false),
+            outliers: Some(OutlierMethod::default()),
+            max_outlier_fraction: Some(DEFAULT_MAX_OUTLIER_FRACTION),
+            max_runs: Some(DEFAULT_MAX_RUNS),
+            drop_caches_command: Some(DEFAULT_DROP_CACHES.to_string()),
             ..Self::empty()
         }
     }
@@ -2,10 +2,28 @@
 #![allow(clippy::needless_pass_by_value)]
 use std::time::Duration;
 
-use minijinja::{value::ViaDeserialize, Environment};
+use minijinja::{
+    value::{ValueKind, ViaDeserialize},
+    Environment, Error, Value,
+};
+use serde::Deserialize;
+
+use super::style::{paint, stdout_supports_colors, GREEN, RED};
+
+/// Number of decimals used by the human-friendly filters unless specified.
+const DEFAULT_PRECISION: usize = 2;
+
+/// Binary byte units, in increasing order of magnitude.
+const BYTE_UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// Decimal count suffixes, in increasing order of magnitude.
+const COUNT_UNITS: &[&str] = &["", " k", " M", " G", " T"];
+
+/// Time units and their length in seconds, in decreasing order of magnitude.
+const TIME_UNITS: &[(&str, f64)] = &[("s", 1.0), ("ms", 1e-3), ("µs", 1e-6), ("ns", 1e-9)];
 
 /// Compute an average over a slice of floating point numbers.
-fn average(values: Vec<f64>) -> f64 {
+pub(super) fn average(values: Vec<f64>) -> f64 {
     if values.is_empty() {
         return 0.0;
     }
@@ -34,6 +52,103 @@ fn as_secs(value: ViaDeserialize<Duration>) -> f64 {
     value.as_secs_f64()
 }
 
+/// Interpret a template value as a number of seconds.
+/// Accepts both plain numbers and serialized `Duration`s.
+fn to_seconds(value: Value) -> Result<f64, Error> {
+    if value.kind() == ValueKind::Number {
+        return f64::try_from(value);
+    }
+    Ok(Duration::deserialize(value)?.as_secs_f64())
+}
+
+/// Format a number of bytes using the largest fitting binary unit, e.g. `1.50 KiB`.
+pub(super) fn human_bytes(value: f64, precision: Option<usize>) -> String {
+    let precision = precision.unwrap_or(DEFAULT_PRECISION);
+    let mut scaled = value;
+    let mut unit_index = 0;
+    while scaled.abs() >= 1024.0 && unit_index + 1 < BYTE_UNITS.len() {
+        scaled /= 1024.0;
+        unit_index += 1;
+    }
+    let unit = BYTE_UNITS[unit_index];
+    if unit_index == 0 {
+        // Fractional bytes are not meaningful.
+        return format!("{scaled:.0} {unit}");
+    }
+    format!("{scaled:.precision$} {unit}")
+}
+
+/// Format a count using the largest fitting decimal suffix, e.g. `1.23 G`.
+pub(super) fn human_count(value: f64, precision: Option<usize>) -> String {
+    let precision = precision.unwrap_or(DEFAULT_PRECISION);
+    let mut scaled = value;
+    let mut unit_index = 0;
+    while scaled.abs() >= 1000.0 && unit_index + 1 < COUNT_UNITS.len() {
+        scaled /= 1000.0;
+        unit_index += 1;
+    }
+    if unit_index == 0 {
+        return format!("{scaled:.0}");
+    }
+    format!("{scaled:.precision$}{}", COUNT_UNITS[unit_index])
+}
+
+/// Format a duration using the largest fitting unit, e.g. `203.84 ms`.
+/// The value is either a `Duration` or a number of seconds.
+fn human_duration(value: Value, precision: Option<usize>) -> Result<String, Error> {
+    Ok(human_seconds(to_seconds(value)?, precision))
+}
+
+/// Format a number of seconds using the largest fitting unit, e.g. `203.84 ms`.
+pub(super) fn human_seconds(seconds: f64, precision: Option<usize>) -> String {
+    let precision = precision.unwrap_or(DEFAULT_PRECISION);
+    let (unit, length) = TIME_UNITS
+        .iter()
+        .find(|(_, length)| seconds.abs() >= *length)
+        .unwrap_or(&TIME_UNITS[TIME_UNITS.len() - 1]);
+    format!("{:.precision$} {unit}", seconds / length)
+}
+
+/// Format a number with an explicit sign, e.g. `+1.50`.
+pub(super) fn signed(value: f64, precision: Option<usize>) -> String {
+    let precision = precision.unwrap_or(DEFAULT_PRECISION);
+    format!("{value:+.precision$}")
+}
+
+/// Relative change from `base` to `value` in percent, if defined.
+fn relative_change(value: f64, base: f64) -> Option<f64> {
+    (base != 0.0).then(|| (value - base) / base.abs() * 100.0)
+}
+
+/// Format the relative change from `base` to `value`, e.g. `+12.50%`.
+pub(super) fn percent_change(value: f64, base: f64, precision: Option<usize>) -> String {
+    relative_change(value, base).map_or_else(
+        || "n/a".to_string(),
+        |change| format!("{}%", signed(change, precision)),
+    )
+}
+
+/// Color for the change from `base` to `value`: green for improvements,
+/// red for regressions and none if there is no (defined) change.
+pub(super) fn delta_color(value: f64, base: f64, lower_is_better: bool) -> Option<&'static str> {
+    match relative_change(value, base) {
+        Some(change) if change != 0.0 && (change < 0.0) == lower_is_better => Some(GREEN),
+        Some(change) if change != 0.0 => Some(RED),
+        _ => None,
+    }
+}
+
+/// Format the relative change from `base` to `value`, colored green for
+/// improvements and red for regressions, if stdout supports colors.
+/// Lower values are considered better unless `lower_is_better` is `false`.
+fn delta(value: f64, base: f64, lower_is_better: Option<bool>, precision: Option<usize>) -> String {
+    let text = percent_change(value, base, precision);
+    delta_color(value, base, lower_is_better.unwrap_or(true)).map_or_else(
+        || text.clone(),
+        |color| paint(&text, color, stdout_supports_colors()),
+    )
+}
+
 /// Add all custom filters to a templating engine.
 pub(super) fn add_filters_to_engine(engine: &mut Environment) {
     engine.add_filter("avg", average);
@@ -41,4 +156,10 @@ pub(super) fn add_filters_to_engine(engine: &mut Environment) {
     engine.add_filter("as_secs", as_secs);
     engine.add_filter("as_kb", bytes_to_kb);
     engine.add_filter("as_mb", bytes_to_mb);
+    engine.add_filter("human_bytes", human_bytes);
+    engine.add_filter("human_count", human_count);
+    engine.add_filter("human_duration", human_duration);
+    engine.add_filter("signed", signed);
+    engine.add_filter("percent_change", percent_change);
+    engine.add_filter("delta", delta);
 }
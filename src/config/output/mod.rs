@@ -1,9 +1,11 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
-use crate::measurement::Results;
+use crate::measurement::{BenchmarkComparison, Comparison, RefResults, Results, Saved, Summary};
+use crate::{cgroup, counters, io_accounting};
 use anyhow::{anyhow, Result};
 use filters::add_filters_to_engine;
 use minijinja::Environment;
+use serde::Serialize;
 
 #[cfg(test)]
 mod tests;
@@ -11,6 +13,13 @@ mod tests;
 /// Custom Jinja filters for use in templates.
 mod filters;
 
+/// Terminal styling helpers.
+mod style;
+
+/// Built-in rich terminal output.
+mod terminal;
+pub use terminal::Terminal;
+
 /// Output template name
 const OUTPUT_TEMPLATE: &str = "output";
 
@@ -21,6 +30,117 @@ pub struct Formatter<'a> {
     engine: Environment<'a>,
 }
 
+/// Values available to the output template.
+#[derive(Serialize)]
+struct TemplateContext<'a> {
+    /// Name of the benchmark.
+    name: &'a str,
+    /// Parameter values of the benchmark.
+    params: &'a BTreeMap<String, String>,
+    /// Results of the benchmark: the aggregate values and the individual `runs`.
+    #[serde(flatten)]
+    summary: &'a Summary,
+}
+
+/// How measurement results are presented.
+#[derive(Debug)]
+pub enum Output<'a> {
+    /// Render each result with a user-supplied template.
+    Template(Box<Formatter<'a>>),
+    /// Render a built-in comparison table.
+    Terminal(Terminal),
+}
+
+impl Output<'_> {
+    /// Create the output for the given template, which may use the given
+    /// custom metrics and benchmark parameters, or the terminal output if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template fails to validate.
+    pub fn new(
+        output_template: Option<String>,
+        metric_names: &[&str],
+        param_names: &[&str],
+        higher_is_better: impl IntoIterator<Item = String>,
+    ) -> Result<Self> {
+        output_template.map_or_else(
+            || {
+                Ok(Self::Terminal(
+                    Terminal::for_stdout().with_higher_is_better(higher_is_better),
+                ))
+            },
+            |template| {
+                Formatter::with_variables(template, metric_names, param_names)
+                    .map(|formatter| Self::Template(Box::new(formatter)))
+            },
+        )
+    }
+
+    /// Render saved results, either of a comparison or of a single reference.
+    ///
+    /// # Errors
+    ///
+    /// Surfaces any error encountered in the templating engine.
+    pub fn render_saved(&self, saved: &Saved) -> Result<String> {
+        match saved {
+            Saved::Comparison(comparison) => self.render_comparison(comparison),
+            Saved::Run(results) => self.render_run(results),
+        }
+    }
+
+    /// Render the results of measuring a single reference.
+    ///
+    /// # Errors
+    ///
+    /// Surfaces any error encountered in the templating engine.
+    pub fn render_run(&self, results: &RefResults) -> Result<String> {
+        match self {
+            Self::Template(formatter) => {
+                let grouped = results.benchmarks.len() > 1;
+                let mut lines = Vec::new();
+                for benchmark in &results.benchmarks {
+                    if grouped {
+                        lines.push(format!("{}:", benchmark.name));
+                    }
+                    lines.push(formatter.render_summary(
+                        &benchmark.name,
+                        &benchmark.params,
+                        &benchmark.summary,
+                    )?);
+                }
+                Ok(lines.join("\n"))
+            }
+            Self::Terminal(terminal) => Ok(terminal.render_run(results)),
+        }
+    }
+
+    /// Render the results of a comparison.
+    ///
+    /// # Errors
+    ///
+    /// Surfaces any error encountered in the templating engine.
+    pub fn render_comparison(&self, comparison: &Comparison) -> Result<String> {
+        match self {
+            Self::Template(formatter) => {
+                let grouped = comparison.benchmarks.len() > 1;
+                let mut lines = Vec::new();
+                for benchmark in &comparison.benchmarks {
+                    if grouped {
+                        lines.push(format!("{}:", benchmark.name));
+                    }
+                    for summary in [&benchmark.base, &benchmark.head] {
+                        lines.push(formatter.render_benchmark(benchmark, summary)?);
+                    }
+                }
+                Ok(lines.join("\n"))
+            }
+            Self::Terminal(terminal) => Ok(terminal.render(comparison)),
+        }
+    }
+}
+
+/// Collect all (nested) field names of a serialized struct, joined by `.`.
 fn extract_struct_fields(value: &serde_json::Value) -> HashSet<String> {
     match value {
         serde_json::Value::Object(object) => object
@@ -43,9 +163,33 @@ impl Formatter<'_> {
     /// # Errors
     ///
     /// Returns an error if the template fails to validate.
-    fn validate_output_template(self) -> Result<Self> {
+    fn validate_output_template(self, metric_names: &[&str], param_names: &[&str]) -> Result<Self> {
         let template = self.engine.get_template(OUTPUT_TEMPLATE)?;
-        let default_results = &Results::default();
+        let default_summary = Summary::from_runs(vec![Results {
+            metrics: metric_names
+                .iter()
+                .map(|name| ((*name).to_string(), 0.0))
+                .collect(),
+            counters: counters::names()
+                .map(|name| (name.to_string(), 0.0))
+                .collect(),
+            io: io_accounting::names()
+                .map(|name| (name.to_string(), 0.0))
+                .collect(),
+            cgroup: cgroup::names()
+                .map(|name| (name.to_string(), 0.0))
+                .collect(),
+            ..Results::default()
+        }]);
+        let default_params = param_names
+            .iter()
+            .map(|name| ((*name).to_string(), String::new()))
+            .collect();
+        let default_results = &TemplateContext {
+            name: "",
+            params: &default_params,
+            summary: &default_summary,
+        };
 
         let template_vars = template.undeclared_variables(true);
         let available_vars = extract_struct_fields(&serde_json::to_value(default_results)?);
@@ -65,11 +209,25 @@ impl Formatter<'_> {
     ///
     /// Surfaces any error encountered in the internal engine.
     pub fn from_template_string(output_template: String) -> Result<Self> {
+        Self::with_variables(output_template, &[], &[])
+    }
+
+    /// Create a template engine populated with the output template,
+    /// which may use the given custom metrics and benchmark parameters.
+    ///
+    /// # Errors
+    ///
+    /// Surfaces any error encountered in the internal engine.
+    pub fn with_variables(
+        output_template: String,
+        metric_names: &[&str],
+        param_names: &[&str],
+    ) -> Result<Self> {
         let mut engine = Environment::new();
         engine.add_template_owned(OUTPUT_TEMPLATE.to_string(), output_template)?;
         add_filters_to_engine(&mut engine);
 
-        Self { engine }.validate_output_template()
+        Self { engine }.validate_output_template(metric_names, param_names)
     }
 
     /// Use the engine to render the output template using measurement results.
@@ -77,7 +235,46 @@ impl Formatter<'_> {
     /// # Errors
     ///
     /// Surfaces any error encountered in the internal engine.
-    pub fn render_results(&self, results: Results) -> Result<String> {
-        Ok(self.engine.get_template(OUTPUT_TEMPLATE)?.render(results)?)
+    pub fn render_results(&self, results: &Results) -> Result<String> {
+        let context = TemplateContext {
+            name: "",
+            params: &BTreeMap::new(),
+            summary: &Summary::from_runs(vec![results.clone()]),
+        };
+        Ok(self.engine.get_template(OUTPUT_TEMPLATE)?.render(context)?)
+    }
+
+    /// Use the engine to render the output template using
+    /// the results of one side of a benchmark comparison.
+    ///
+    /// # Errors
+    ///
+    /// Surfaces any error encountered in the internal engine.
+    pub fn render_benchmark(
+        &self,
+        benchmark: &BenchmarkComparison,
+        summary: &Summary,
+    ) -> Result<String> {
+        self.render_summary(&benchmark.name, &benchmark.params, summary)
+    }
+
+    /// Use the engine to render the output template using
+    /// the results of a benchmark on one reference.
+    ///
+    /// # Errors
+    ///
+    /// Surfaces any error encountered in the internal engine.
+    pub fn render_summary(
+        &self,
+        name: &str,
+        params: &BTreeMap<String, String>,
+        summary: &Summary,
+    ) -> Result<String> {
+        let context = TemplateContext {
+            name,
+            params,
+            summary,
+        };
+        Ok(self.engine.get_template(OUTPUT_TEMPLATE)?.render(context)?)
     }
 }
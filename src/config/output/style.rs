@@ -0,0 +1,32 @@
+use std::io::IsTerminal;
+
+/// ANSI escape code for green text.
+pub(super) const GREEN: &str = "\x1b[32m";
+
+/// ANSI escape code for red text.
+pub(super) const RED: &str = "\x1b[31m";
+
+/// ANSI escape code for bold text.
+pub(super) const BOLD: &str = "\x1b[1m";
+
+/// ANSI escape code resetting the text style.
+pub(super) const RESET: &str = "\x1b[0m";
+
+/// Whether colored output is allowed, see <https://no-color.org>.
+pub(super) fn colors_enabled() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Whether colored output is allowed and stdout is an interactive terminal.
+pub(super) fn stdout_supports_colors() -> bool {
+    colors_enabled() && std::io::stdout().is_terminal()
+}
+
+/// Wrap text in an ANSI style, or return it unchanged if `enabled` is false.
+pub(super) fn paint(text: &str, style: &str, enabled: bool) -> String {
+    if enabled {
+        format!("{style}{text}{RESET}")
+    } else {
+        text.to_string()
+    }
+}
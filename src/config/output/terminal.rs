@@ -0,0 +1,524 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+use super::filters::{
+    average, delta_color, human_bytes, human_count, human_seconds, percent_change, signed,
+};
+use super::style::{paint, stdout_supports_colors, BOLD};
+use crate::adaptive::Outcome;
+use crate::cgroup::{IO_READS, IO_READ_BYTES, IO_WRITES, IO_WRITE_BYTES, MEMORY_PEAK};
+use crate::counters::{Source as CounterSource, TASK_CLOCK};
+use crate::measurement::{BenchmarkComparison, Comparison, RefResults, Results};
+
+/// Maximum width of a histogram bar in characters.
+const HISTOGRAM_WIDTH: usize = 30;
+
+/// Maximum number of bins in the run time histogram.
+const HISTOGRAM_BINS: usize = 10;
+
+/// A single measurement row in the comparison table.
+struct Row {
+    /// Name of the measurement.
+    label: String,
+    /// Value for the base reference.
+    base: f64,
+    /// Value for the head reference.
+    head: f64,
+    /// Formatting of the values.
+    format: fn(f64) -> String,
+    /// Whether a decrease is an improvement.
+    lower_is_better: bool,
+}
+
+impl Row {
+    /// Rows to show for a comparison.
+    fn from_results(
+        base: &Results,
+        head: &Results,
+        higher_is_better: &HashSet<String>,
+    ) -> Vec<Self> {
+        /// Largest value in a slice, zero if empty.
+        fn peak(values: &[f64]) -> f64 {
+            values.iter().copied().fold(0.0, f64::max)
+        }
+        let builtin = [
+            (
+                "wall time",
+                base.wall_time.as_secs_f64(),
+                head.wall_time.as_secs_f64(),
+                (|value| human_seconds(value, None)) as fn(f64) -> String,
+            ),
+            (
+                "cpu (avg)",
+                average(base.cpu.clone()),
+                average(head.cpu.clone()),
+                |value| format!("{value:.1} %"),
+            ),
+            (
+                "ram (avg)",
+                average(base.ram.clone()),
+                average(head.ram.clone()),
+                |value| human_bytes(value, None),
+            ),
+            ("ram (peak)", peak(&base.ram), peak(&head.ram), |value| {
+                human_bytes(value, None)
+            }),
+        ]
+        .map(|(label, base, head, format)| Self {
+            label: label.to_string(),
+            base,
+            head,
+            format,
+            lower_is_better: true,
+        });
+
+        // Metrics missing on one side cannot be compared.
+        let metric_names: BTreeSet<&String> = base
+            .metrics
+            .keys()
+            .filter(|name| head.metrics.contains_key(*name))
+            .collect();
+        let metrics = metric_names.into_iter().map(|name| Self {
+            label: name.clone(),
+            base: base.metrics[name],
+            head: head.metrics[name],
+            format: |value| format!("{value}"),
+            lower_is_better: !higher_is_better.contains(name),
+        });
+
+        // Counters and I/O statistics are only compared if available on both sides.
+        // Counters taken from the resource usage of child processes are marked as such.
+        let rusage: HashSet<String> = base
+            .counter_sources
+            .iter()
+            .chain(&head.counter_sources)
+            .filter(|(_, source)| **source == CounterSource::Rusage)
+            .map(|(name, _)| name.replace('_', " "))
+            .collect();
+        let counters = Self::shared(&base.counters, &head.counters, |name| {
+            if name == TASK_CLOCK {
+                |value| human_seconds(value, None)
+            } else {
+                |value| human_count(value, None)
+            }
+        })
+        .map(move |row| {
+            if rusage.contains(&row.label) {
+                Self {
+                    label: format!("{} (rusage)", row.label),
+                    ..row
+                }
+            } else {
+                row
+            }
+        });
+        // Most programs never cancel writes, which is not worth a row.
+        let io = Self::shared(&base.io, &head.io, |name| {
+            if name.ends_with("_syscalls") {
+                |value| human_count(value, None)
+            } else {
+                |value| human_bytes(value, None)
+            }
+        })
+        .filter(|row| row.base != 0.0 || row.head != 0.0);
+        let cgroup = Self::shared(&base.cgroup, &head.cgroup, |name| match name {
+            MEMORY_PEAK | IO_READ_BYTES | IO_WRITE_BYTES => |value| human_bytes(value, None),
+            IO_READS | IO_WRITES => |value| human_count(value, None),
+            _ => |value| human_seconds(value, None),
+        })
+        .map(|row| Self {
+            label: format!("cgroup {}", row.label),
+            ..row
+        });
+
+        builtin
+            .into_iter()
+            .chain(counters)
+            .chain(io)
+            .chain(cgroup)
+            .chain(metrics)
+            .collect()
+    }
+
+    /// Rows for the values present in both maps, labelled by their names.
+    fn shared<'a>(
+        base: &'a BTreeMap<String, f64>,
+        head: &'a BTreeMap<String, f64>,
+        format: fn(&str) -> fn(f64) -> String,
+    ) -> impl Iterator<Item = Self> + 'a {
+        base.iter()
+            .filter_map(|(name, base)| Some((name, *base, *head.get(name)?)))
+            .map(move |(name, base, head)| Self {
+                label: name.replace('_', " "),
+                base,
+                head,
+                format: format(name),
+                lower_is_better: true,
+            })
+    }
+}
+
+/// Note after a reference whose results were reused from the cache.
+const fn cached_note(cached: bool) -> &'static str {
+    if cached {
+        " (cached results of an identical tree)"
+    } else {
+        ""
+    }
+}
+
+/// Built-in renderer printing an aligned comparison table
+/// and a histogram of run times.
+#[derive(Debug)]
+pub struct Terminal {
+    /// Whether to highlight output using ANSI colors.
+    colors: bool,
+    /// Names of custom metrics where higher values are better.
+    higher_is_better: HashSet<String>,
+}
+
+impl Terminal {
+    /// Create a renderer, with or without colors.
+    #[must_use]
+    pub fn new(colors: bool) -> Self {
+        Self {
+            colors,
+            higher_is_better: HashSet::new(),
+        }
+    }
+
+    /// Create a renderer using colors only if stdout is a terminal
+    /// and `NO_COLOR` is not set.
+    #[must_use]
+    pub fn for_stdout() -> Self {
+        Self::new(stdout_supports_colors())
+    }
+
+    /// Treat increases of the given custom metrics as improvements.
+    #[must_use]
+    pub fn with_higher_is_better(mut self, metric_names: impl IntoIterator<Item = String>) -> Self {
+        self.higher_is_better.extend(metric_names);
+        self
+    }
+
+    /// Render a comparison of two measurement results, grouped by benchmark.
+    #[must_use]
+    pub fn render(&self, comparison: &Comparison) -> String {
+        let Comparison {
+            base_ref,
+            head_ref,
+            benchmarks,
+            head_reused,
+            base_cached,
+            head_cached,
+        } = comparison;
+        let mut output = String::new();
+        let _ = writeln!(output, "base: {base_ref}{}", cached_note(*base_cached));
+        if *head_reused {
+            let _ = writeln!(
+                output,
+                "head: {head_ref} (no changes in paths, reusing results of base)"
+            );
+        } else {
+            let _ = writeln!(output, "head: {head_ref}{}", cached_note(*head_cached));
+        }
+        if let Some(governor) = benchmarks
+            .first()
+            .and_then(|benchmark| benchmark.base.aggregate.cpu_governor.as_ref())
+        {
+            let _ = writeln!(output, "cpu governor: {governor}");
+        }
+        for benchmark in benchmarks {
+            let _ = writeln!(output);
+            output.push_str(&self.benchmark(benchmark));
+        }
+
+        let mut groups: Vec<&str> = Vec::new();
+        for benchmark in benchmarks
+            .iter()
+            .filter(|benchmark| !benchmark.params.is_empty())
+        {
+            if !groups.contains(&benchmark.group.as_str()) {
+                groups.push(&benchmark.group);
+            }
+        }
+        for group in groups {
+            let cases: Vec<&BenchmarkComparison> = benchmarks
+                .iter()
+                .filter(|benchmark| benchmark.group == group)
+                .collect();
+            let _ = writeln!(output);
+            output.push_str(&self.scaling(group, &cases));
+        }
+        output
+    }
+
+    /// Render the results of measuring a single reference, grouped by benchmark.
+    #[must_use]
+    pub fn render_run(&self, results: &RefResults) -> String {
+        let mut output = String::new();
+        let _ = writeln!(
+            output,
+            "ref: {}{}",
+            results.git_ref,
+            cached_note(results.cached)
+        );
+        if let Some(governor) = results
+            .benchmarks
+            .first()
+            .and_then(|benchmark| benchmark.summary.aggregate.cpu_governor.as_ref())
+        {
+            let _ = writeln!(output, "cpu governor: {governor}");
+        }
+        for benchmark in &results.benchmarks {
+            let summary = &benchmark.summary;
+            let _ = writeln!(output);
+            let title = format!("{} ({} runs)", benchmark.name, summary.runs.len());
+            let _ = writeln!(output, "{}", paint(&title, BOLD, self.colors));
+            // Both sides are the same, only the base values are shown.
+            let rows = Row::from_results(
+                &summary.aggregate,
+                &summary.aggregate,
+                &self.higher_is_better,
+            );
+            let width = rows
+                .iter()
+                .map(|row| row.label.chars().count())
+                .max()
+                .unwrap_or_default();
+            for row in rows {
+                let _ = writeln!(output, "{:<width$}  {}", row.label, (row.format)(row.base));
+            }
+        }
+        output
+    }
+
+    /// Render how the wall time delta of a benchmark scales across its parameters.
+    fn scaling(&self, group: &str, cases: &[&BenchmarkComparison]) -> String {
+        let mut output = String::new();
+        let title = format!("{group}: wall time by parameters");
+        let _ = writeln!(output, "{}", paint(&title, BOLD, self.colors));
+        let rows: Vec<Row> = cases
+            .iter()
+            .map(|case| {
+                let params: Vec<String> = case
+                    .params
+                    .iter()
+                    .map(|(param, value)| format!("{param}={value}"))
+                    .collect();
+                Row {
+                    label: params.join(", "),
+                    base: case.base.aggregate.wall_time.as_secs_f64(),
+                    head: case.head.aggregate.wall_time.as_secs_f64(),
+                    format: |value| human_seconds(value, None),
+                    lower_is_better: true,
+                }
+            })
+            .collect();
+        output.push_str(&self.table(&rows));
+        output
+    }
+
+    /// Render the comparison of a single benchmark.
+    fn benchmark(&self, benchmark: &BenchmarkComparison) -> String {
+        let BenchmarkComparison {
+            name,
+            base,
+            head,
+            adaptive,
+            ..
+        } = benchmark;
+        let mut output = String::new();
+        let cold = if base.aggregate.caches_dropped.is_some() {
+            ", cold"
+        } else {
+            ""
+        };
+        let title = format!(
+            "{name} ({} vs {} runs{cold})",
+            base.runs.len(),
+            head.runs.len()
+        );
+        let _ = writeln!(output, "{}", paint(&title, BOLD, self.colors));
+        if [base, head]
+            .iter()
+            .any(|summary| summary.aggregate.caches_dropped == Some(false))
+        {
+            let _ = writeln!(
+                output,
+                "warning: caches could not be dropped before every run"
+            );
+        }
+        if !(base.rejected.is_empty() && head.rejected.is_empty()) {
+            // Runs are numbered from 1 for humans.
+            let runs = |rejected: &[usize]| {
+                let numbers: Vec<String> = rejected
+                    .iter()
+                    .map(|index| format!("#{}", index + 1))
+                    .collect();
+                if numbers.is_empty() {
+                    "none".to_string()
+                } else {
+                    numbers.join(", ")
+                }
+            };
+            let _ = writeln!(
+                output,
+                "rejected outliers: base {}; head {}",
+                runs(&base.rejected),
+                runs(&head.rejected)
+            );
+        }
+        if let Some(Outcome {
+            runs,
+            interval,
+            reached,
+        }) = adaptive
+        {
+            let interval = interval.map_or_else(
+                || "unknown".to_string(),
+                |(low, high)| format!("{}% to {}%", signed(low, None), signed(high, None)),
+            );
+            let status = if *reached {
+                format!("{runs} runs needed")
+            } else {
+                format!("target not reached after {runs} runs")
+            };
+            let _ = writeln!(
+                output,
+                "adaptive: {status}, 95% CI of wall time delta {interval}"
+            );
+        }
+        output.push_str(&self.table(&Row::from_results(
+            &base.aggregate,
+            &head.aggregate,
+            &self.higher_is_better,
+        )));
+        let _ = writeln!(output);
+        output.push_str(&self.histogram(&base.wall_times(), &head.wall_times()));
+        if let (Some(base_drift), Some(head_drift)) = (base.drift(), head.drift()) {
+            let _ = writeln!(
+                output,
+                "drift (later vs earlier runs): base {}, head {}",
+                signed(base_drift, None) + "%",
+                signed(head_drift, None) + "%"
+            );
+        }
+        output
+    }
+
+    /// Render rows as a table with right-aligned value columns.
+    fn table(&self, rows: &[Row]) -> String {
+        let cells: Vec<[String; 4]> = rows
+            .iter()
+            .map(|row| {
+                [
+                    row.label.clone(),
+                    (row.format)(row.base),
+                    (row.format)(row.head),
+                    percent_change(row.head, row.base, None),
+                ]
+            })
+            .collect();
+        let header = ["", "base", "head", "delta"].map(str::to_string);
+        let mut widths = header.clone().map(|cell| cell.chars().count());
+        for row in &cells {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut output = String::new();
+        let [label, base, head, delta] = &header;
+        let header_line = format!(
+            "{label:<0$}  {base:>1$}  {head:>2$}  {delta:>3$}",
+            widths[0], widths[1], widths[2], widths[3]
+        );
+        let _ = writeln!(output, "{}", paint(&header_line, BOLD, self.colors));
+        for (row, [label, base, head, delta]) in rows.iter().zip(&cells) {
+            let delta = format!("{delta:>0$}", widths[3]);
+            let delta = delta_color(row.head, row.base, row.lower_is_better)
+                .map_or_else(|| delta.clone(), |color| paint(&delta, color, self.colors));
+            let _ = writeln!(
+                output,
+                "{label:<0$}  {base:>1$}  {head:>2$}  {delta}",
+                widths[0], widths[1], widths[2]
+            );
+        }
+        output
+    }
+
+    /// Render a histogram of base and head run times, in seconds.
+    /// Empty if no runs were recorded.
+    fn histogram(&self, base: &[f64], head: &[f64]) -> String {
+        if base.is_empty() && head.is_empty() {
+            return String::new();
+        }
+        let min = base
+            .iter()
+            .chain(head)
+            .copied()
+            .fold(f64::INFINITY, f64::min);
+        let max = base
+            .iter()
+            .chain(head)
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let bins = HISTOGRAM_BINS.min(base.len() + head.len()).max(1);
+        #[allow(clippy::cast_precision_loss)]
+        let bin_width = (max - min) / bins as f64;
+
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let count = |values: &[f64]| {
+            let mut counts = vec![0usize; bins];
+            for value in values {
+                let index = if bin_width > 0.0 {
+                    ((value - min) / bin_width) as usize
+                } else {
+                    0
+                };
+                counts[index.min(bins - 1)] += 1;
+            }
+            counts
+        };
+        let (base_counts, head_counts) = (count(base), count(head));
+        let max_count = base_counts
+            .iter()
+            .chain(&head_counts)
+            .copied()
+            .max()
+            .unwrap_or(1);
+        let bar = |count: usize| "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(max_count));
+
+        #[allow(clippy::cast_precision_loss)]
+        let labels: Vec<String> = (0..bins)
+            .map(|index| human_seconds(bin_width.mul_add(index as f64, min), None))
+            .collect();
+        let label_width = labels
+            .iter()
+            .map(|label| label.chars().count())
+            .chain(std::iter::once("run time".len()))
+            .max()
+            .unwrap_or_default();
+
+        let mut output = String::new();
+        let header = format!(
+            "{:<label_width$}  {:<HISTOGRAM_WIDTH$}  head",
+            "run time", "base"
+        );
+        let _ = writeln!(output, "{}", paint(&header, BOLD, self.colors));
+        for ((label, base_count), head_count) in labels.iter().zip(base_counts).zip(head_counts) {
+            let line = format!(
+                "{label:>label_width$}  {:<HISTOGRAM_WIDTH$}  {}",
+                bar(base_count),
+                bar(head_count)
+            );
+            let _ = writeln!(output, "{}", line.trim_end());
+        }
+        output
+    }
+}
@@ -6,7 +6,7 @@ mod template {
 
     use super::Formatter;
 
-    fn test_output(template: &str, results: Results, expected: &str) {
+    fn test_output(template: &str, results: &Results, expected: &str) {
         let formatter = Formatter::from_template_string(template.to_string()).unwrap();
         let rendered_results = formatter.render_results(results).unwrap();
         assert_eq!(rendered_results, expected.to_string());
@@ -14,7 +14,7 @@ mod template {
 
     #[test]
     fn trivial() {
-        test_output("No output", Results::default(), "No output");
+        test_output("No output", &Results::default(), "No output");
     }
 
     #[test]
@@ -28,11 +28,64 @@ mod template {
         assert_eq!(err.to_string(), expected_error);
     }
 
+    #[test]
+    fn metrics() {
+        let template = "ops: {{ metrics.ops }}".to_string();
+        assert!(Formatter::from_template_string(template.clone()).is_err());
+        let formatter = Formatter::with_variables(template, &["ops"], &[]).unwrap();
+        let results = Results {
+            metrics: [("ops".to_string(), 1500.0)].into(),
+            ..Results::default()
+        };
+        assert_eq!(formatter.render_results(&results).unwrap(), "ops: 1500.0");
+    }
+
+    #[test]
+    fn benchmarks() {
+        let formatter =
+            Formatter::from_template_string("{{ name }}: {{ runs | length }} runs".to_string())
+                .unwrap();
+        let mut comparison = super::terminal::comparison();
+        assert_eq!(
+            crate::config::Output::Template(Box::new(formatter))
+                .render_comparison(&comparison)
+                .unwrap(),
+            "bench: 1 runs\nbench: 1 runs"
+        );
+        let formatter =
+            Formatter::from_template_string("{{ wall_time | as_millis }}".to_string()).unwrap();
+        comparison
+            .benchmarks
+            .push(super::terminal::comparison().benchmarks.remove(0));
+        comparison.benchmarks[1].name = "other".to_string();
+        assert_eq!(
+            crate::config::Output::Template(Box::new(formatter))
+                .render_comparison(&comparison)
+                .unwrap(),
+            "bench:\n200\n100\nother:\n200\n100"
+        );
+    }
+
+    #[test]
+    fn params() {
+        let template = "size {{ params.size }}".to_string();
+        assert!(Formatter::from_template_string(template.clone()).is_err());
+        let formatter = Formatter::with_variables(template, &[], &["size"]).unwrap();
+        let mut comparison = super::terminal::comparison();
+        comparison.benchmarks[0].params = [("size".to_string(), "10".to_string())].into();
+        assert_eq!(
+            crate::config::Output::Template(Box::new(formatter))
+                .render_comparison(&comparison)
+                .unwrap(),
+            "size 10\nsize 10"
+        );
+    }
+
     #[test]
     fn nested_values() {
         test_output(
             "Ran in {{ wall_time.secs }} whole seconds",
-            Results::default(),
+            &Results::default(),
             "Ran in 0 whole seconds",
         );
     }
@@ -44,7 +97,7 @@ mod template {
         fn jinja_filters() {
             test_output(
                 "Min CPU usage: {{ cpu | min }}",
-                Results {
+                &Results {
                     cpu: vec![30.0, 50.0, 10.0, 40.0],
                     ..Results::default()
                 },
@@ -56,7 +109,7 @@ mod template {
         fn custom_cpu_filters() {
             test_output(
                 "Avg CPU usage: {{ cpu | avg }}",
-                Results {
+                &Results {
                     cpu: vec![30.0, 10.0],
                     ..Results::default()
                 },
@@ -68,7 +121,7 @@ mod template {
         fn custom_ram_filters() {
             test_output(
                 "avg kb: {{ ram | avg | as_kb }}",
-                Results {
+                &Results {
                     ram: vec![1024.0, 2048.0],
                     ..Results::default()
                 },
@@ -80,12 +133,411 @@ mod template {
         fn custom_time_filters() {
             test_output(
                 "millis: {{ wall_time | as_millis }}",
-                Results {
+                &Results {
                     wall_time: std::time::Duration::from_millis(12345),
                     ..Results::default()
                 },
                 "millis: 12345",
             );
         }
+
+        #[test]
+        fn human_bytes() {
+            test_output(
+                "{{ 512 | human_bytes }}, {{ 1536 | human_bytes }}, {{ 3221225472 | human_bytes(1) }}",
+                &Results::default(),
+                "512 B, 1.50 KiB, 3.0 GiB",
+            );
+        }
+
+        #[test]
+        fn human_count() {
+            test_output(
+                "{{ 999 | human_count }}, {{ 1500 | human_count }}, {{ 2345678901 | human_count(1) }}",
+                &Results::default(),
+                "999, 1.50 k, 2.3 G",
+            );
+        }
+
+        #[test]
+        fn human_duration() {
+            test_output(
+                "{{ wall_time | human_duration }} / {{ 0.000012 | human_duration(0) }}",
+                &Results {
+                    wall_time: std::time::Duration::from_nanos(203_841_729),
+                    ..Results::default()
+                },
+                "203.84 ms / 12 µs",
+            );
+        }
+
+        #[test]
+        fn signed_and_percent_change() {
+            test_output(
+                "{{ 1.5 | signed }} {{ -2 | signed(0) }} {{ 12 | percent_change(10) }} {{ 1 | percent_change(0) }}",
+                &Results::default(),
+                "+1.50 -2 +20.00% n/a",
+            );
+        }
+
+        #[test]
+        fn delta() {
+            // Redirected output, as in tests, is not colored.
+            let colored = crate::config::output::style::stdout_supports_colors();
+            let (green, red, reset) = if colored {
+                ("\x1b[32m", "\x1b[31m", "\x1b[0m")
+            } else {
+                ("", "", "")
+            };
+            test_output(
+                "{{ 8 | delta(10) }} {{ 8 | delta(10, false, 0) }} {{ 10 | delta(10) }}",
+                &Results::default(),
+                &format!("{green}-20.00%{reset} {red}-20%{reset} +0.00%"),
+            );
+        }
+    }
+}
+
+mod terminal {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use crate::adaptive::Outcome;
+    use crate::config::Terminal;
+    use crate::counters::Source as CounterSource;
+    use crate::measurement::{BenchmarkComparison, Comparison, Results, Summary};
+    use crate::outliers::{Method, Outliers};
+
+    pub(super) fn comparison() -> Comparison {
+        let base = Results {
+            wall_time: Duration::from_millis(200),
+            cpu: vec![50.0],
+            ram: vec![1024.0, 3072.0],
+            metrics: [("ops".to_string(), 10.0), ("latency".to_string(), 2.0)].into(),
+            counters: BTreeMap::new(),
+            counter_sources: BTreeMap::new(),
+            io: BTreeMap::new(),
+            cgroup: BTreeMap::new(),
+            order: 0,
+            cpu_governor: None,
+            caches_dropped: None,
+        };
+        let head = Results {
+            wall_time: Duration::from_millis(100),
+            cpu: vec![100.0],
+            ram: vec![2048.0, 4096.0],
+            metrics: [("ops".to_string(), 20.0), ("latency".to_string(), 1.0)].into(),
+            counters: BTreeMap::new(),
+            counter_sources: BTreeMap::new(),
+            io: BTreeMap::new(),
+            cgroup: BTreeMap::new(),
+            order: 1,
+            cpu_governor: None,
+            caches_dropped: None,
+        };
+        Comparison {
+            base_ref: "main".to_string(),
+            head_ref: "HEAD".to_string(),
+            benchmarks: vec![BenchmarkComparison {
+                name: "bench".to_string(),
+                group: "bench".to_string(),
+                params: BTreeMap::new(),
+                base: Summary::from_runs(vec![base]),
+                head: Summary::from_runs(vec![head]),
+                adaptive: None,
+            }],
+            head_reused: false,
+            base_cached: false,
+            head_cached: false,
+        }
+    }
+
+    #[test]
+    fn table() {
+        let rendered = Terminal::new(false).render(&comparison());
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "base: main");
+        assert_eq!(lines[1], "head: HEAD");
+        assert_eq!(lines[3], "bench (1 vs 1 runs)");
+        assert_eq!(lines[4], "                 base       head     delta");
+        assert_eq!(lines[5], "wall time   200.00 ms  100.00 ms   -50.00%");
+        assert_eq!(lines[6], "cpu (avg)      50.0 %    100.0 %  +100.00%");
+        assert_eq!(lines[8], "ram (peak)   3.00 KiB   4.00 KiB   +33.33%");
+    }
+
+    #[test]
+    fn reused() {
+        let reused = Comparison {
+            head_reused: true,
+            ..comparison()
+        };
+        let rendered = Terminal::new(false).render(&reused);
+        assert_eq!(
+            rendered.lines().nth(1),
+            Some("head: HEAD (no changes in paths, reusing results of base)")
+        );
+        let cached = Comparison {
+            base_cached: true,
+            ..comparison()
+        };
+        let rendered = Terminal::new(false).render(&cached);
+        assert_eq!(
+            rendered.lines().next(),
+            Some("base: main (cached results of an identical tree)")
+        );
+    }
+
+    #[test]
+    fn histogram() {
+        let rendered = Terminal::new(false).render(&comparison());
+        let histogram: Vec<&str> = rendered.lines().skip(12).collect();
+        let bar = "#".repeat(30);
+        assert_eq!(histogram.len(), 3);
+        assert!(histogram[0].starts_with("run time"));
+        assert_eq!(
+            histogram[1],
+            format!("100.00 ms  {}  {bar}", " ".repeat(30))
+        );
+        assert_eq!(histogram[2], format!("150.00 ms  {bar}"));
+
+        // Adaptive measuring may stop before any run.
+        let mut empty = comparison();
+        empty.benchmarks[0].base = Summary::from_runs(Vec::new());
+        empty.benchmarks[0].head = Summary::from_runs(Vec::new());
+        let rendered = Terminal::new(false).render(&empty);
+        assert!(!rendered.contains("run time"));
+    }
+
+    #[test]
+    fn metrics() {
+        let rendered = Terminal::new(true)
+            .with_higher_is_better(["ops".to_string()])
+            .render(&comparison());
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(
+            lines[9],
+            "latency             2          1  \x1b[32m -50.00%\x1b[0m"
+        );
+        assert_eq!(
+            lines[10],
+            "ops                10         20  \x1b[32m+100.00%\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn grouped_runs() {
+        let run = |millis| Results {
+            wall_time: Duration::from_millis(millis),
+            ..Results::default()
+        };
+        let mut comparison = comparison();
+        comparison.benchmarks.push(BenchmarkComparison {
+            name: "other".to_string(),
+            group: "other".to_string(),
+            params: BTreeMap::new(),
+            base: Summary::from_runs(vec![run(100), run(120), run(140)]),
+            head: Summary::from_runs(vec![run(100), run(100)]),
+            adaptive: None,
+        });
+        let rendered = Terminal::new(false).render(&comparison);
+        let lines: Vec<&str> = rendered.lines().collect();
+        let other = lines
+            .iter()
+            .position(|line| *line == "other (3 vs 2 runs)")
+            .unwrap();
+        assert_eq!(
+            lines[other + 2],
+            "wall time   120.00 ms  100.00 ms  -16.67%"
+        );
+        // Five runs in total, one bin per run.
+        let histogram = &lines[other + 7..lines.len() - 1];
+        assert_eq!(histogram.len(), 6);
+        assert!(histogram[1].ends_with(&"#".repeat(30)));
+    }
+
+    #[test]
+    fn cpu_governor() {
+        let mut comparison = comparison();
+        comparison.benchmarks[0].base.aggregate.cpu_governor = Some("powersave".to_string());
+        let rendered = Terminal::new(false).render(&comparison);
+        assert_eq!(rendered.lines().nth(2), Some("cpu governor: powersave"));
+    }
+
+    #[test]
+    fn cold() {
+        let run = |caches_dropped| Results {
+            caches_dropped: Some(caches_dropped),
+            ..Results::default()
+        };
+        let mut comparison = comparison();
+        comparison.benchmarks[0].base = Summary::from_runs(vec![run(true), run(true)]);
+        comparison.benchmarks[0].head = Summary::from_runs(vec![run(true), run(false)]);
+        let rendered = Terminal::new(false).render(&comparison);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[3], "bench (2 vs 2 runs, cold)");
+        assert_eq!(
+            lines[4],
+            "warning: caches could not be dropped before every run"
+        );
+    }
+
+    #[test]
+    fn counters() {
+        let run = |instructions, task_clock| Results {
+            counters: [
+                ("instructions".to_string(), instructions),
+                ("task_clock".to_string(), task_clock),
+            ]
+            .into(),
+            ..Results::default()
+        };
+        let mut comparison = comparison();
+        comparison.benchmarks[0].base = Summary::from_runs(vec![run(2e9, 0.5)]);
+        comparison.benchmarks[0].head = Summary::from_runs(vec![run(1e9, 0.25)]);
+        let rendered = Terminal::new(false).render(&comparison);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[9], "instructions     2.00 G     1.00 G  -50.00%");
+        assert_eq!(lines[10], "task clock    500.00 ms  250.00 ms  -50.00%");
+
+        // Counters taken from the resource usage are told apart from counted events.
+        comparison.benchmarks[0].head.aggregate.counter_sources =
+            [("task_clock".to_string(), CounterSource::Rusage)].into();
+        let rendered = Terminal::new(false).render(&comparison);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(
+            lines[10],
+            "task clock (rusage)  500.00 ms  250.00 ms  -50.00%"
+        );
+    }
+
+    #[test]
+    fn io() {
+        let run = |read_bytes| Results {
+            io: [
+                ("read_bytes".to_string(), read_bytes),
+                ("read_syscalls".to_string(), 12.0),
+                ("cancelled_write_bytes".to_string(), 0.0),
+            ]
+            .into(),
+            ..Results::default()
+        };
+        let mut comparison = comparison();
+        comparison.benchmarks[0].base = Summary::from_runs(vec![run(1024.0)]);
+        comparison.benchmarks[0].head = Summary::from_runs(vec![run(3072.0)]);
+        let rendered = Terminal::new(false).render(&comparison);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[9], "read bytes     1.00 KiB  3.00 KiB  +200.00%");
+        assert_eq!(lines[10], "read syscalls        12        12    +0.00%");
+        assert!(!rendered.contains("cancelled"));
+    }
+
+    #[test]
+    fn outliers() {
+        let outliers = Outliers {
+            method: Method::Iqr,
+            threshold: 1.5,
+            max_fraction: 0.1,
+        };
+        let runs = |millis: &[u64]| {
+            let runs = millis
+                .iter()
+                .map(|millis| Results {
+                    wall_time: Duration::from_millis(*millis),
+                    ..Results::default()
+                })
+                .collect();
+            Summary::from_runs(runs).reject_outliers(&outliers)
+        };
+        let mut comparison = comparison();
+        comparison.benchmarks[0].base = runs(&[100, 101, 99, 100, 500]);
+        comparison.benchmarks[0].head = runs(&[100, 101, 99, 100, 100]);
+        assert_eq!(
+            comparison.benchmarks[0].base.aggregate.wall_time,
+            Duration::from_millis(100)
+        );
+        let rendered = Terminal::new(false).render(&comparison);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[4], "rejected outliers: base #5; head none");
+    }
+
+    #[test]
+    fn adaptive() {
+        let mut comparison = comparison();
+        comparison.benchmarks[0].adaptive = Some(Outcome {
+            runs: 12,
+            interval: Some((-51.5, -48.5)),
+            reached: true,
+        });
+        let rendered = Terminal::new(false).render(&comparison);
+        assert_eq!(
+            rendered.lines().nth(4),
+            Some("adaptive: 12 runs needed, 95% CI of wall time delta -51.50% to -48.50%")
+        );
+    }
+
+    #[test]
+    fn drift() {
+        let run = |millis, order| Results {
+            wall_time: Duration::from_millis(millis),
+            order,
+            ..Results::default()
+        };
+        let mut comparison = comparison();
+        comparison.benchmarks[0].base =
+            Summary::from_runs(vec![run(100, 0), run(100, 2), run(120, 4), run(120, 6)]);
+        comparison.benchmarks[0].head =
+            Summary::from_runs(vec![run(150, 7), run(100, 1), run(100, 3), run(150, 5)]);
+        let rendered = Terminal::new(false).render(&comparison);
+        assert_eq!(
+            rendered.lines().last(),
+            Some("drift (later vs earlier runs): base +20.00%, head +50.00%")
+        );
+    }
+
+    #[test]
+    fn parameter_scaling() {
+        let case = |size: &str, base, head| {
+            let run = |millis| Results {
+                wall_time: Duration::from_millis(millis),
+                ..Results::default()
+            };
+            BenchmarkComparison {
+                name: format!("sort[size={size}]"),
+                group: "sort".to_string(),
+                params: [("size".to_string(), size.to_string())].into(),
+                base: Summary::from_runs(vec![run(base)]),
+                head: Summary::from_runs(vec![run(head)]),
+                adaptive: None,
+            }
+        };
+        let comparison = Comparison {
+            base_ref: "main".to_string(),
+            head_ref: "HEAD".to_string(),
+            benchmarks: vec![case("10", 10, 10), case("100", 100, 150)],
+            head_reused: false,
+            base_cached: false,
+            head_cached: false,
+        };
+        let rendered = Terminal::new(false).render(&comparison);
+        let lines: Vec<&str> = rendered.lines().collect();
+        let scaling = lines
+            .iter()
+            .position(|line| *line == "sort: wall time by parameters")
+            .unwrap();
+        assert_eq!(
+            lines[scaling + 2],
+            "size=10    10.00 ms   10.00 ms   +0.00%"
+        );
+        assert_eq!(
+            lines[scaling + 3],
+            "size=100  100.00 ms  150.00 ms  +50.00%"
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let rendered = Terminal::new(true).render(&comparison());
+        assert!(rendered.contains("\x1b[32m"));
+        assert!(rendered.contains("\x1b[31m"));
     }
 }
@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::sync::Once;
+
+/// Kind of a counted event, see `perf_event_open(2)`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Counted by the CPU, may be unavailable in virtual machines or without permissions.
+    Hardware,
+    /// Counted by the kernel.
+    Software,
+}
+
+/// Where the value of a counter comes from, from the most to the least precise.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// A hardware event counted by the CPU.
+    Hardware,
+    /// A software event counted by the kernel.
+    Software,
+    /// The resource usage of terminated children, if the event could not be counted.
+    Rusage,
+}
+
+impl From<Kind> for Source {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Hardware => Self::Hardware,
+            Kind::Software => Self::Software,
+        }
+    }
+}
+
+/// An event counted for the measured process.
+struct Event {
+    /// Name of the counter in the results.
+    name: &'static str,
+    /// Kind of the event.
+    kind: Kind,
+    /// Event identifier within its kind.
+    config: u64,
+}
+
+/// All events counted, in the order they are reported.
+const EVENTS: [Event; 6] = [
+    Event {
+        name: "instructions",
+        kind: Kind::Hardware,
+        config: 1,
+    },
+    Event {
+        name: "cycles",
+        kind: Kind::Hardware,
+        config: 0,
+    },
+    Event {
+        name: "branch_misses",
+        kind: Kind::Hardware,
+        config: 5,
+    },
+    Event {
+        name: "cache_misses",
+        kind: Kind::Hardware,
+        config: 3,
+    },
+    Event {
+        name: TASK_CLOCK,
+        kind: Kind::Software,
+        config: 1,
+    },
+    Event {
+        name: PAGE_FAULTS,
+        kind: Kind::Software,
+        config: 2,
+    },
+];
+
+/// CPU time of the measured process in seconds.
+pub const TASK_CLOCK: &str = "task_clock";
+
+/// Number of page faults of the measured process.
+pub const PAGE_FAULTS: &str = "page_faults";
+
+/// Names of all counters that may be reported.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    EVENTS.iter().map(|event| event.name)
+}
+
+/// Warns once if performance counters are unavailable.
+static UNAVAILABLE_WARNING: Once = Once::new();
+
+/// Warn that the events of the given kinds could not be opened,
+/// naming the source used instead.
+fn warn_unavailable(hardware: bool, software: bool) {
+    UNAVAILABLE_WARNING.call_once(|| {
+        let unavailable = if hardware {
+            "Performance"
+        } else {
+            "Software performance"
+        };
+        if software {
+            eprintln!(
+                "Warning: {unavailable} counters are unavailable, task clock and page faults \
+                 are taken from the resource usage of child processes (rusage)."
+            );
+        } else if hardware {
+            eprintln!(
+                "Warning: Hardware performance counters are unavailable, \
+                 only software counters are collected."
+            );
+        }
+    });
+}
+
+/// Performance counters of the child processes spawned by the current thread.
+///
+/// Uses `perf_event_open` on Linux: the counters are opened disabled on the
+/// current thread, inherited by child processes and enabled when a child executes
+/// its program, so that neither the current process nor its threads are counted.
+/// Events that cannot be opened fall back to the resource usage of
+/// terminated children, which covers CPU time and page faults.
+pub struct Counters {
+    /// Opened counters by name, with their kind.
+    events: Vec<(&'static str, Kind, File)>,
+    /// Resource usage of terminated children when counting started.
+    usage: libc::rusage,
+}
+
+impl Counters {
+    /// Start counting events of processes spawned afterwards by the current thread.
+    /// Processes spawned by other threads are not counted.
+    #[must_use]
+    pub fn start() -> Self {
+        let events: Vec<(&'static str, Kind, File)> = EVENTS
+            .iter()
+            .filter_map(|event| Some((event.name, event.kind, perf::open(event)?)))
+            .collect();
+        let unavailable = |kind: Kind| {
+            EVENTS
+                .iter()
+                .filter(|event| event.kind == kind)
+                .any(|event| !events.iter().any(|(name, ..)| *name == event.name))
+        };
+        warn_unavailable(unavailable(Kind::Hardware), unavailable(Kind::Software));
+        Self {
+            events,
+            usage: children_usage(),
+        }
+    }
+
+    /// Stop counting and read the counters, with the source of each.
+    /// Must be called after the measured process has been waited for.
+    #[must_use]
+    pub fn read(self) -> (BTreeMap<String, f64>, BTreeMap<String, Source>) {
+        let mut sources = BTreeMap::new();
+        let mut values: BTreeMap<String, f64> = self
+            .events
+            .into_iter()
+            .filter_map(|(name, kind, file)| {
+                let value = perf::read(file)?;
+                // The task clock is counted in nanoseconds.
+                let value = if name == TASK_CLOCK {
+                    value / 1e9
+                } else {
+                    value
+                };
+                sources.insert(name.to_string(), kind.into());
+                Some((name.to_string(), value))
+            })
+            .collect();
+
+        let usage = children_usage();
+        #[allow(clippy::cast_precision_loss)]
+        let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+        let cpu_time = seconds(usage.ru_utime) + seconds(usage.ru_stime)
+            - seconds(self.usage.ru_utime)
+            - seconds(self.usage.ru_stime);
+        #[allow(clippy::cast_precision_loss)]
+        let page_faults = (usage.ru_minflt + usage.ru_majflt
+            - self.usage.ru_minflt
+            - self.usage.ru_majflt) as f64;
+        for (name, value) in [(TASK_CLOCK, cpu_time), (PAGE_FAULTS, page_faults)] {
+            values.entry(name.to_string()).or_insert_with(|| {
+                sources.insert(name.to_string(), Source::Rusage);
+                value
+            });
+        }
+        (values, sources)
+    }
+}
+
+/// Resource usage of all terminated and waited for children of this process.
+fn children_usage() -> libc::rusage {
+    // SAFETY: `rusage` is plain old data, and `getrusage` only writes to it.
+    unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        libc::getrusage(libc::RUSAGE_CHILDREN, &raw mut usage);
+        usage
+    }
+}
+
+/// Access to `perf_event_open`.
+#[cfg(target_os = "linux")]
+mod perf {
+    use super::{Event, Kind};
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+
+    /// `PERF_TYPE_HARDWARE`
+    const TYPE_HARDWARE: u32 = 0;
+    /// `PERF_TYPE_SOFTWARE`
+    const TYPE_SOFTWARE: u32 = 1;
+    /// Start the counter disabled.
+    const DISABLED: u64 = 1;
+    /// Count child processes created after opening.
+    const INHERIT: u64 = 1 << 1;
+    /// Do not count kernel code, which requires more privileges.
+    const EXCLUDE_KERNEL: u64 = 1 << 5;
+    /// Do not count hypervisor code.
+    const EXCLUDE_HV: u64 = 1 << 6;
+    /// Enable the counter when the process executes a program.
+    const ENABLE_ON_EXEC: u64 = 1 << 12;
+    /// `PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING`
+    const READ_FORMAT_TIMES: u64 = 1 | 2;
+    /// `PERF_FLAG_FD_CLOEXEC`
+    const FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+
+    /// First version of `struct perf_event_attr`, which all kernels accept.
+    #[repr(C)]
+    #[derive(Default)]
+    struct Attributes {
+        /// Type of the event.
+        kind: u32,
+        /// Size of this struct.
+        size: u32,
+        /// Event identifier within its type.
+        config: u64,
+        /// Sampling period, unused.
+        sample_period: u64,
+        /// Sample contents, unused.
+        sample_type: u64,
+        /// Values returned when reading the counter.
+        read_format: u64,
+        /// Flag bits.
+        flags: u64,
+        /// Wakeup events, unused.
+        wakeup_events: u32,
+        /// Breakpoint type, unused.
+        bp_type: u32,
+        /// Extension of `config`, unused.
+        config1: u64,
+        /// Further extension of `config`, unused.
+        config2: u64,
+    }
+
+    /// Open a disabled counter for an event on the current thread.
+    pub(super) fn open(event: &Event) -> Option<File> {
+        let attributes = Attributes {
+            kind: match event.kind {
+                Kind::Hardware => TYPE_HARDWARE,
+                Kind::Software => TYPE_SOFTWARE,
+            },
+            size: u32::try_from(std::mem::size_of::<Attributes>()).ok()?,
+            config: event.config,
+            read_format: READ_FORMAT_TIMES,
+            flags: DISABLED | INHERIT | EXCLUDE_KERNEL | EXCLUDE_HV | ENABLE_ON_EXEC,
+            ..Attributes::default()
+        };
+        // SAFETY: The attributes outlive the call, and a returned
+        // file descriptor is owned by nobody else.
+        unsafe {
+            let fd = libc::syscall(
+                libc::SYS_perf_event_open,
+                &raw const attributes,
+                0,
+                -1,
+                -1,
+                FLAG_FD_CLOEXEC,
+            );
+            let fd = i32::try_from(fd).ok().filter(|fd| *fd >= 0)?;
+            Some(File::from_raw_fd(fd))
+        }
+    }
+
+    /// Read a counter, scaled up if it could not count all the time
+    /// because the CPU was running other counters.
+    pub(super) fn read(mut file: File) -> Option<f64> {
+        let mut buffer = [0u8; 24];
+        file.read_exact(&mut buffer).ok()?;
+        let [value, enabled, running] = [0, 1, 2].map(|index| {
+            let bytes = buffer[index * 8..(index + 1) * 8]
+                .try_into()
+                .unwrap_or_default();
+            u64::from_ne_bytes(bytes)
+        });
+        if running == 0 {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        Some(value as f64 * (enabled as f64 / running as f64))
+    }
+}
+
+/// Fallback without `perf_event_open`: no events can be opened.
+#[cfg(not(target_os = "linux"))]
+mod perf {
+    use super::Event;
+    use std::fs::File;
+
+    /// Performance counters are only supported on Linux.
+    pub(super) const fn open(_event: &Event) -> Option<File> {
+        None
+    }
+
+    /// Performance counters are only supported on Linux.
+    pub(super) const fn read(_file: File) -> Option<f64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counters, PAGE_FAULTS, TASK_CLOCK};
+
+    #[test]
+    fn software_counters() {
+        let counters = Counters::start();
+        let status = std::process::Command::new("true").status().unwrap();
+        assert!(status.success());
+        let (values, sources) = counters.read();
+        assert!(values[TASK_CLOCK] >= 0.0);
+        assert!(values[PAGE_FAULTS] > 0.0);
+        // Every counter has a source, whether perf events or resource usage.
+        assert!(values.keys().eq(sources.keys()));
+    }
+}
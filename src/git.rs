@@ -1,13 +1,218 @@
-use anyhow::{anyhow, Result};
-use git2::Repository;
-use std::path::PathBuf;
+use anyhow::{anyhow, Context as _, Result};
+use git2::{
+    build::CheckoutBuilder, DiffOptions, IndexAddOption, Oid, Repository, Signature,
+    SubmoduleUpdateOptions,
+};
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Reference keeping the snapshot of the working tree reachable while it is not restored.
+pub const SNAPSHOT_REF: &str = "refs/perfdiff/working-tree";
+
+/// Name of the lock file in the git directory.
+const LOCK_FILE: &str = "perfdiff.lock";
 
 /// Git repository context. Wraps the `git2::Repository` type.
+///
+/// Methods changing the working tree first acquire a lock on the repository,
+/// failing if another running process holds it.
 pub struct Context {
     /// The wrapped repository.
     pub repo: Repository,
     /// Path to the git repository in the file system.
     pub path: PathBuf,
+    /// Lock on the repository, acquired before changing it and held until dropped.
+    lock: OnceLock<Lock>,
+    /// HEAD of each initialized submodule before the first checkout, recursively,
+    /// by path relative to the working tree.
+    submodule_heads: OnceLock<Vec<(PathBuf, OriginalHead)>>,
+}
+
+/// Lock file in the git directory, preventing concurrent runs in the same repository.
+/// Contains the PID and command line of the holder.
+///
+/// The file is locked with `flock`, which the kernel releases when the holder exits,
+/// so that a file left behind by a process that no longer runs does not lock anything.
+struct Lock {
+    /// Path of the lock file.
+    path: PathBuf,
+    /// The locked file, unless another lock in the same process holds it.
+    file: Option<File>,
+}
+
+impl Lock {
+    /// Lock the lock file, creating it if needed.
+    fn acquire(git_dir: &Path) -> Result<Self> {
+        let path = git_dir.join(LOCK_FILE);
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    let mut contents = String::new();
+                    let _ = file.read_to_string(&mut contents);
+                    let (pid, command_line) = contents.split_once('\n').unwrap_or((&contents, ""));
+                    if pid.trim().parse() == Ok(std::process::id()) {
+                        return Ok(Self { path, file: None });
+                    }
+                    return Err(anyhow!(
+                        "Repository is locked by process {} (`{}`)",
+                        pid.trim(),
+                        command_line.trim()
+                    ));
+                }
+                Err(TryLockError::Error(err)) => {
+                    return Err(anyhow!("Failed to lock {}: {err}", path.display()));
+                }
+            }
+            // The previous holder removes the file before releasing it,
+            // so the locked file may no longer be the lock file.
+            let locked = file.metadata()?.ino();
+            if std::fs::metadata(&path).is_ok_and(|metadata| metadata.ino() == locked) {
+                let command_line: Vec<String> = std::env::args().collect();
+                file.set_len(0)
+                    .and_then(|()| {
+                        writeln!(file, "{}\n{}", std::process::id(), command_line.join(" "))
+                    })
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                return Ok(Self {
+                    path,
+                    file: Some(file),
+                });
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Removed before the file is closed and thereby unlocked, see `acquire`.
+        if self.file.is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// What HEAD of a repository points to.
+fn original_head(repo: &Repository) -> Result<OriginalHead> {
+    let head = repo.find_reference("HEAD")?;
+    // A symbolic HEAD points to a branch, which may not exist yet.
+    if let Some(name) = head.symbolic_target() {
+        return Ok(OriginalHead::Branch(name.to_string()));
+    }
+    head.target()
+        .map(OriginalHead::Detached)
+        .ok_or_else(|| anyhow!("HEAD is neither a branch nor a commit"))
+}
+
+/// Collect HEAD of each initialized submodule, recursively, by path prefixed with `prefix`.
+fn submodule_heads(
+    repo: &Repository,
+    prefix: &Path,
+    heads: &mut Vec<(PathBuf, OriginalHead)>,
+) -> Result<()> {
+    for submodule in repo.submodules()? {
+        // Uninitialized submodules have no repository.
+        let Ok(submodule_repo) = submodule.open() else {
+            continue;
+        };
+        let path = prefix.join(submodule.path());
+        heads.push((path.clone(), original_head(&submodule_repo)?));
+        submodule_heads(&submodule_repo, &path, heads)?;
+    }
+    Ok(())
+}
+
+/// Check out the commits recorded for the initialized submodules, recursively,
+/// fetching them if missing. Uninitialized submodules are skipped.
+fn update_submodules(repo: &Repository) -> Result<()> {
+    for mut submodule in repo.submodules()? {
+        let Ok(submodule_repo) = submodule.open() else {
+            continue;
+        };
+        let mut options = SubmoduleUpdateOptions::new();
+        submodule.update(false, Some(&mut options)).map_err(|err| {
+            anyhow!(
+                "Failed to update submodule {}: {err}",
+                submodule.path().display()
+            )
+        })?;
+        update_submodules(&submodule_repo)?;
+    }
+    Ok(())
+}
+
+/// Write the files of a commit into a directory, including those of initialized submodules.
+fn export_commit(repo: &Repository, commit: &git2::Commit, target: &Path) -> Result<()> {
+    std::fs::create_dir_all(target)?;
+    let mut checkout = CheckoutBuilder::new();
+    checkout
+        .force()
+        .recreate_missing(true)
+        .update_index(false)
+        .target_dir(target);
+    repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+
+    let tree = commit.tree()?;
+    for submodule in repo.submodules()? {
+        let Ok(submodule_repo) = submodule.open() else {
+            continue;
+        };
+        // Submodules added after the commit are not part of it.
+        let Ok(entry) = tree.get_path(submodule.path()) else {
+            continue;
+        };
+        let submodule_commit = submodule_repo.find_commit(entry.id()).map_err(|err| {
+            anyhow!(
+                "Commit {} of submodule {} is missing, fetch it first: {err}",
+                entry.id(),
+                submodule.path().display()
+            )
+        })?;
+        export_commit(
+            &submodule_repo,
+            &submodule_commit,
+            &target.join(submodule.path()),
+        )?;
+    }
+    Ok(())
+}
+
+/// What HEAD pointed to before measuring, to be restored afterwards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginalHead {
+    /// A branch, by its full reference name. The branch may be unborn, i.e. have no commits yet.
+    Branch(String),
+    /// A detached commit.
+    Detached(Oid),
+}
+
+impl fmt::Display for OriginalHead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Branch(name) => write!(f, "{name}"),
+            Self::Detached(oid) => write!(f, "detached commit {oid}"),
+        }
+    }
+}
+
+/// Uncommitted changes of a repository, saved so that its working tree can be
+/// cleaned for checkouts and restored afterwards.
+pub struct Snapshot {
+    /// Commit on top of HEAD containing the working tree, including untracked files.
+    pub commit: Oid,
+    /// Tree of the index, i.e. the staged changes.
+    index_tree: Oid,
 }
 
 /// Checks if a git repository is clean, i.e. has no uncommitted, unignored files.
@@ -26,11 +231,17 @@ impl Context {
     ///
     /// Forwards any errors arising from `git2`.
     pub fn checkout(&self, reference: impl AsRef<str>) -> Result<()> {
+        self.lock()?;
         let repo = &self.repo;
         // We don't want to discard uncommitted files.
         if !repo_is_clean(repo)? {
             return Err(anyhow!("Repository contains uncommitted files"));
         }
+        if self.submodule_heads.get().is_none() {
+            let mut heads = Vec::new();
+            submodule_heads(repo, Path::new(""), &mut heads)?;
+            let _ = self.submodule_heads.set(heads);
+        }
         let (object, git_reference) = repo.revparse_ext(reference.as_ref())?;
         repo.checkout_tree(&object, None)?;
         git_reference.map_or_else(
@@ -42,6 +253,169 @@ impl Context {
                 repo.set_head(ref_name)
             },
         )?;
+        update_submodules(repo)?;
+        Ok(())
+    }
+
+    /// Capture what HEAD currently points to.
+    ///
+    /// # Errors
+    ///
+    /// Forwards any errors arising from `git2`, e.g. if HEAD is not a valid reference.
+    pub fn original_head(&self) -> Result<OriginalHead> {
+        original_head(&self.repo)
+    }
+
+    /// Check out what HEAD originally pointed to, as captured by [`Context::original_head`].
+    /// Restoring an unborn branch removes all tracked files.
+    /// Submodules are restored to what their HEAD pointed to before the first checkout.
+    ///
+    /// # Errors
+    ///
+    /// Forwards any errors arising from `git2`, and fails if the repository
+    /// contains uncommitted files.
+    pub fn restore_head(&self, original: &OriginalHead) -> Result<()> {
+        self.restore_superproject_head(original)?;
+        let Some(workdir) = self.repo.workdir() else {
+            return Ok(());
+        };
+        for (path, head) in self.submodule_heads.get().into_iter().flatten() {
+            let submodule = Self::try_from(workdir.join(path))?;
+            if submodule.original_head()? != *head {
+                submodule.restore_head(head)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check out what HEAD of the repository itself originally pointed to.
+    fn restore_superproject_head(&self, original: &OriginalHead) -> Result<()> {
+        let repo = &self.repo;
+        match original {
+            OriginalHead::Branch(name) if repo.find_reference(name).is_err() => {
+                self.lock()?;
+                if !repo_is_clean(repo)? {
+                    return Err(anyhow!("Repository contains uncommitted files"));
+                }
+                let empty_tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+                let mut checkout = CheckoutBuilder::new();
+                checkout.force();
+                repo.checkout_tree(empty_tree.as_object(), Some(&mut checkout))?;
+                repo.set_head(name)?;
+                Ok(())
+            }
+            OriginalHead::Branch(name) => self.checkout(name),
+            OriginalHead::Detached(oid) => self.checkout(oid.to_string()),
+        }
+    }
+
+    /// Save the working tree, including untracked but not ignored files, and the index
+    /// in a [`Snapshot`], then clean the working tree and index to match HEAD.
+    ///
+    /// The snapshot commit is kept reachable by [`SNAPSHOT_REF`] until it is restored.
+    ///
+    /// # Errors
+    ///
+    /// Forwards any errors arising from `git2`, e.g. if the index has conflicts.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.lock()?;
+        let repo = &self.repo;
+        let mut index = repo.index()?;
+        let index_tree = index.write_tree()?;
+        index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+        index.update_all(["*"], None)?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        // Discard the added files, the index on disk is untouched.
+        index.read(true)?;
+
+        let head = repo.head()?.peel_to_commit()?;
+        let signature = Signature::now("git-perfdiff", "git-perfdiff@localhost")?;
+        let commit = repo.commit(
+            None,
+            &signature,
+            &signature,
+            "Working tree snapshot of git-perfdiff",
+            &tree,
+            &[&head],
+        )?;
+        repo.reference(SNAPSHOT_REF, commit, true, "git-perfdiff: snapshot")?;
+
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force().remove_untracked(true);
+        repo.checkout_head(Some(&mut checkout))?;
+        Ok(Snapshot { commit, index_tree })
+    }
+
+    /// Restore the working tree and index saved in a snapshot,
+    /// leaving HEAD untouched. The working tree must be clean.
+    ///
+    /// # Errors
+    ///
+    /// Forwards any errors arising from `git2`.
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<()> {
+        self.lock()?;
+        let repo = &self.repo;
+        let commit = repo.find_commit(snapshot.commit)?;
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force().update_index(false);
+        repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+        let mut index = repo.index()?;
+        index.read_tree(&repo.find_tree(snapshot.index_tree)?)?;
+        index.write()?;
+        repo.find_reference(SNAPSHOT_REF)?.delete()?;
+        Ok(())
+    }
+
+    /// Write the files of a git reference into a separate directory,
+    /// leaving the working tree, index and HEAD of the repository untouched.
+    /// Includes the recorded commits of initialized submodules, recursively.
+    ///
+    /// # Errors
+    ///
+    /// Forwards any errors arising from `git2` or from creating the directory.
+    pub fn export(&self, reference: impl AsRef<str>, target: &Path) -> Result<()> {
+        self.lock()?;
+        let commit = self
+            .repo
+            .revparse_single(reference.as_ref())?
+            .peel_to_commit()?;
+        export_commit(&self.repo, &commit, target)
+    }
+
+    /// Whether any file matching the pathspecs differs between the trees of two commits.
+    ///
+    /// # Errors
+    ///
+    /// Forwards any errors arising from `git2`.
+    pub fn changes_in(&self, base: Oid, head: Oid, paths: &[String]) -> Result<bool> {
+        let base_tree = self.repo.find_commit(base)?.tree()?;
+        let head_tree = self.repo.find_commit(head)?.tree()?;
+        let mut options = DiffOptions::new();
+        for path in paths {
+            options.pathspec(path);
+        }
+        let diff =
+            self.repo
+                .diff_tree_to_tree(Some(&base_tree), Some(&head_tree), Some(&mut options))?;
+        Ok(diff.deltas().len() > 0)
+    }
+
+    /// Directory for temporary files of `git-perfdiff`, inside the git directory.
+    #[must_use]
+    pub fn scratch_dir(&self) -> PathBuf {
+        self.repo.path().join("perfdiff")
+    }
+
+    /// Acquire the lock on the repository, unless already held.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the holder if another running process holds the lock.
+    pub fn lock(&self) -> Result<()> {
+        if self.lock.get().is_none() {
+            let lock = Lock::acquire(self.repo.path())?;
+            let _ = self.lock.set(lock);
+        }
         Ok(())
     }
 
@@ -61,6 +435,8 @@ impl TryFrom<PathBuf> for Context {
         Ok(Self {
             repo: Repository::open(&value)?,
             path: value,
+            lock: OnceLock::new(),
+            submodule_heads: OnceLock::new(),
         })
     }
 }
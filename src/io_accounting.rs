@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::io;
+
+/// Fields of `/proc/<pid>/io` and the names they are reported as.
+const FIELDS: [(&str, &str); 7] = [
+    ("rchar", READ_BYTES),
+    ("wchar", WRITE_BYTES),
+    ("syscr", READ_SYSCALLS),
+    ("syscw", WRITE_SYSCALLS),
+    ("read_bytes", STORAGE_READ_BYTES),
+    ("write_bytes", STORAGE_WRITE_BYTES),
+    ("cancelled_write_bytes", CANCELLED_WRITE_BYTES),
+];
+
+/// Bytes read by read-like system calls, including from pipes and the page cache.
+pub const READ_BYTES: &str = "read_bytes";
+
+/// Bytes written by write-like system calls.
+pub const WRITE_BYTES: &str = "write_bytes";
+
+/// Number of read-like system calls.
+pub const READ_SYSCALLS: &str = "read_syscalls";
+
+/// Number of write-like system calls.
+pub const WRITE_SYSCALLS: &str = "write_syscalls";
+
+/// Bytes actually fetched from storage.
+pub const STORAGE_READ_BYTES: &str = "storage_read_bytes";
+
+/// Bytes sent to storage.
+pub const STORAGE_WRITE_BYTES: &str = "storage_write_bytes";
+
+/// Bytes that were written, but truncated before reaching storage.
+pub const CANCELLED_WRITE_BYTES: &str = "cancelled_write_bytes";
+
+/// Names of all I/O statistics that may be reported.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    FIELDS.iter().map(|(_, name)| *name)
+}
+
+/// Whether a child process has exited, without reaping it.
+///
+/// An exited child stays a zombie until it is waited for,
+/// so its I/O statistics can still be read with [`read`].
+///
+/// # Errors
+///
+/// Returns the OS error if the process is not a child of this process.
+pub fn has_exited(pid: u32) -> io::Result<bool> {
+    let pid = libc::id_t::from(pid);
+    // SAFETY: `siginfo_t` is plain old data, and `waitid` only writes to it.
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        let result = libc::waitid(
+            libc::P_PID,
+            pid,
+            &raw mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        );
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // With `WNOHANG`, the PID stays zero while the child is running.
+        Ok(info.si_pid() != 0)
+    }
+}
+
+/// I/O statistics of a process, by name.
+///
+/// The kernel adds the statistics of waited-for children to their parent,
+/// so reading an exited but not yet reaped process covers its entire
+/// process tree, except for descendants outliving it.
+/// Returns no statistics where `/proc/<pid>/io` is unavailable, e.g. on non-Linux systems.
+#[must_use]
+pub fn read(pid: u32) -> BTreeMap<String, f64> {
+    std::fs::read_to_string(format!("/proc/{pid}/io"))
+        .map(|contents| parse(&contents))
+        .unwrap_or_default()
+}
+
+/// Parse the contents of `/proc/<pid>/io`, skipping unknown fields.
+fn parse(contents: &str) -> BTreeMap<String, f64> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (field, value) = line.split_once(':')?;
+            let (_, name) = FIELDS.iter().find(|(known, _)| *known == field.trim())?;
+            Some(((*name).to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_exited, parse, read, CANCELLED_WRITE_BYTES, READ_BYTES, WRITE_BYTES};
+
+    #[test]
+    fn parsing() {
+        let stats = parse("rchar: 2048\nwchar: 10\nunknown: 1\ncancelled_write_bytes: 0\n");
+        assert_eq!(stats.len(), 3);
+        assert!((stats[READ_BYTES] - 2048.0).abs() < f64::EPSILON);
+        assert!((stats[WRITE_BYTES] - 10.0).abs() < f64::EPSILON);
+        assert!(stats[CANCELLED_WRITE_BYTES].abs() < f64::EPSILON);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn process_tree() {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "head -c 100000 /dev/zero > /dev/null"])
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        while !has_exited(pid).unwrap() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let stats = read(pid);
+        assert!(child.wait().unwrap().success());
+        // Written by `head`, a child of the shell.
+        assert!(stats[WRITE_BYTES] >= 100_000.0);
+    }
+}
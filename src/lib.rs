@@ -11,3 +11,36 @@ pub mod config;
 
 /// Measurement functions
 pub mod measurement;
+
+/// Custom metrics extracted from program output
+pub mod metrics;
+
+/// Performance counters of measured processes
+pub mod counters;
+
+/// I/O statistics of measured processes
+pub mod io_accounting;
+
+/// Transient cgroups containing measured processes
+pub mod cgroup;
+
+/// Detection of outlier runs
+pub mod outliers;
+
+/// Adaptive run counts based on confidence intervals
+pub mod adaptive;
+
+/// Ordering of base and head runs
+pub mod schedule;
+
+/// Execution of a comparison
+pub mod runner;
+
+/// Cache of results of identical trees
+pub mod cache;
+
+/// Interruption by signals, killing the running command
+pub mod signals;
+
+/// Implementation of the subcommands
+pub mod commands;
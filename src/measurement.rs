@@ -1,9 +1,19 @@
-use crate::config::{Command, Validated};
-use anyhow::{anyhow, Result};
-use serde::Serialize;
-use std::process::ExitStatus;
+use crate::adaptive::Outcome;
+use crate::cgroup::Cgroup;
+use crate::config::{Benchmark, Command, Hook, Hooks, Validated};
+use crate::counters::{Counters, Source as CounterSource};
+use crate::io_accounting;
+use crate::metrics::{CapturedOutput, Extractor};
+use crate::outliers::Outliers;
+use crate::signals;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
 use std::sync::LazyLock;
-use std::thread::sleep;
+use std::thread::{sleep, JoinHandle};
 use std::time::{Duration, Instant};
 use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, RefreshKind, System};
 
@@ -20,7 +30,7 @@ struct ProbeMeasurement {
 }
 
 /// Measurement results
-#[derive(Serialize, Default)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Results {
     /// Wall run time of process.
     pub wall_time: Duration,
@@ -28,11 +38,246 @@ pub struct Results {
     pub cpu: Vec<f64>,
     /// RAM utilization in Bytes.
     pub ram: Vec<f64>,
+    /// Custom metrics extracted from the program output, by name.
+    pub metrics: BTreeMap<String, f64>,
+    /// Position of the run among all runs of the comparison, starting at 0.
+    pub order: usize,
+    /// Frequency governor of the CPUs the program ran on, if known.
+    pub cpu_governor: Option<String>,
+    /// Performance counters by name, e.g. `instructions`, if enabled.
+    /// Task clock is in seconds.
+    pub counters: BTreeMap<String, f64>,
+    /// Source of each performance counter by name, telling counted events
+    /// apart from values taken from the resource usage of child processes.
+    #[serde(default)]
+    pub counter_sources: BTreeMap<String, CounterSource>,
+    /// I/O statistics of the process tree by name, e.g. `read_bytes`, if available.
+    pub io: BTreeMap<String, f64>,
+    /// Statistics of the transient cgroup of the run by name, e.g. `memory_peak`,
+    /// if running in cgroups.
+    pub cgroup: BTreeMap<String, f64>,
+    /// In cold mode, whether filesystem caches were dropped before the run.
+    /// For aggregates, whether they were dropped before every run.
+    pub caches_dropped: Option<bool>,
+}
+
+/// Results of all runs of a benchmark on one reference.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Summary {
+    /// Aggregate over all runs: mean wall time and metrics,
+    /// and the probe samples of all runs combined.
+    #[serde(flatten)]
+    pub aggregate: Results,
+    /// Results of the individual runs.
+    pub runs: Vec<Results>,
+    /// Indices of the runs rejected as outliers, which the aggregate excludes.
+    pub rejected: Vec<usize>,
+}
+
+/// Results of a single benchmark on both sides of a comparison.
+#[derive(Serialize, Deserialize)]
+pub struct BenchmarkComparison {
+    /// Name of the benchmark.
+    pub name: String,
+    /// Name of the benchmark definition, shared by all its parameter combinations.
+    pub group: String,
+    /// Parameter values of the benchmark.
+    pub params: BTreeMap<String, String>,
+    /// Results of the base reference.
+    pub base: Summary,
+    /// Results of the head reference.
+    pub head: Summary,
+    /// How adaptive measuring ended, if the run count was adaptive.
+    pub adaptive: Option<Outcome>,
+}
+
+/// Results of measuring both sides of a comparison.
+#[derive(Serialize, Deserialize)]
+pub struct Comparison {
+    /// Name of the base reference.
+    pub base_ref: String,
+    /// Name of the head reference.
+    pub head_ref: String,
+    /// Results for each benchmark.
+    pub benchmarks: Vec<BenchmarkComparison>,
+    /// Whether head was not measured and reuses the results of base,
+    /// because no file matching the `paths` filter changed.
+    #[serde(default)]
+    pub head_reused: bool,
+    /// Whether the results of base were reused from a previous measurement of an identical tree.
+    #[serde(default)]
+    pub base_cached: bool,
+    /// Whether the results of head were reused from a previous measurement of an identical tree.
+    #[serde(default)]
+    pub head_cached: bool,
+}
+
+/// Results of a single benchmark on one reference.
+#[derive(Serialize, Deserialize)]
+pub struct BenchmarkResults {
+    /// Name of the benchmark.
+    pub name: String,
+    /// Name of the benchmark definition, shared by all its parameter combinations.
+    pub group: String,
+    /// Parameter values of the benchmark.
+    pub params: BTreeMap<String, String>,
+    /// Results of all runs.
+    pub summary: Summary,
+}
+
+/// Results of measuring a single reference.
+#[derive(Serialize, Deserialize)]
+pub struct RefResults {
+    /// Name of the reference.
+    pub git_ref: String,
+    /// Results for each benchmark.
+    pub benchmarks: Vec<BenchmarkResults>,
+    /// Whether the results were reused from a previous measurement of an identical tree.
+    #[serde(default)]
+    pub cached: bool,
+}
+
+/// Results saved to a file, to be shown later.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Saved {
+    /// Results of comparing two references.
+    Comparison(Comparison),
+    /// Results of measuring a single reference.
+    Run(RefResults),
+}
+
+impl Saved {
+    /// Write the results to a JSON file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to save results to {}", path.display()))
+    }
+
+    /// Read results from a JSON file written by [`Saved::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or does not contain saved results.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read results from {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("{} does not contain saved results", path.display()))
+    }
+}
+
+impl Summary {
+    /// Aggregate the results of several runs.
+    #[must_use]
+    pub fn from_runs(runs: Vec<Results>) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let count = runs.len().max(1) as u32;
+        let wall_time = runs.iter().map(|run| run.wall_time).sum::<Duration>() / count;
+        let cpu = runs.iter().flat_map(|run| run.cpu.clone()).collect();
+        let ram = runs.iter().flat_map(|run| run.ram.clone()).collect();
+
+        let metrics = average_by_name(runs.iter().map(|run| &run.metrics));
+        let counters = average_by_name(runs.iter().map(|run| &run.counters));
+        // The least precise source of any run.
+        let mut counter_sources = BTreeMap::new();
+        for (name, source) in runs.iter().flat_map(|run| &run.counter_sources) {
+            counter_sources
+                .entry(name.clone())
+                .and_modify(|least: &mut CounterSource| *least = (*least).max(*source))
+                .or_insert(*source);
+        }
+        let io = average_by_name(runs.iter().map(|run| &run.io));
+        let cgroup = average_by_name(runs.iter().map(|run| &run.cgroup));
+
+        Self {
+            aggregate: Results {
+                wall_time,
+                cpu,
+                ram,
+                metrics,
+                counters,
+                counter_sources,
+                io,
+                cgroup,
+                order: 0,
+                cpu_governor: runs.first().and_then(|run| run.cpu_governor.clone()),
+                caches_dropped: runs
+                    .iter()
+                    .filter_map(|run| run.caches_dropped)
+                    .reduce(|all, dropped| all && dropped),
+            },
+            runs,
+            rejected: Vec::new(),
+        }
+    }
+
+    /// Exclude the runs detected as outliers from the aggregate.
+    /// All runs are kept, with the rejected ones listed in `rejected`.
+    #[must_use]
+    pub fn reject_outliers(self, outliers: &Outliers) -> Self {
+        let rejected = outliers.rejected(&self.runs);
+        if rejected.is_empty() {
+            return self;
+        }
+        let kept = self
+            .runs
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !rejected.contains(index))
+            .map(|(_, run)| run.clone())
+            .collect();
+        Self {
+            aggregate: Self::from_runs(kept).aggregate,
+            runs: self.runs,
+            rejected,
+        }
+    }
+
+    /// Relative change in percent of the mean wall time of the later half
+    /// of the runs compared to the earlier half, in execution order.
+    /// Indicates whether the machine got slower or faster during measuring.
+    #[must_use]
+    pub fn drift(&self) -> Option<f64> {
+        if self.runs.len() < 2 {
+            return None;
+        }
+        let mut runs: Vec<&Results> = self.runs.iter().collect();
+        runs.sort_by_key(|run| run.order);
+        let (early, late) = runs.split_at(runs.len() / 2);
+        #[allow(clippy::cast_precision_loss)]
+        let mean = |runs: &[&Results]| {
+            runs.iter()
+                .map(|run| run.wall_time.as_secs_f64())
+                .sum::<f64>()
+                / runs.len() as f64
+        };
+        let (early, late) = (mean(early), mean(late));
+        (early > 0.0).then(|| (late - early) / early * 100.0)
+    }
+
+    /// Wall times of all runs, in seconds.
+    #[must_use]
+    pub fn wall_times(&self) -> Vec<f64> {
+        self.runs
+            .iter()
+            .map(|run| run.wall_time.as_secs_f64())
+            .collect()
+    }
 }
 
 impl Results {
     /// Perform necessary aggregations on the measurements to create final results.
-    fn from_measurements(wall_time: Duration, measurements: Vec<ProbeMeasurement>) -> Self {
+    fn from_measurements(
+        wall_time: Duration,
+        measurements: Vec<ProbeMeasurement>,
+        metrics: BTreeMap<String, f64>,
+    ) -> Self {
         #[allow(clippy::cast_precision_loss)]
         let (cpu, ram) = measurements
             .into_iter()
@@ -42,10 +287,33 @@ impl Results {
             wall_time,
             cpu,
             ram,
+            metrics,
+            counters: BTreeMap::new(),
+            counter_sources: BTreeMap::new(),
+            io: BTreeMap::new(),
+            cgroup: BTreeMap::new(),
+            order: 0,
+            cpu_governor: None,
+            caches_dropped: None,
         }
     }
 }
 
+/// Average values by name, over all maps containing the name.
+fn average_by_name<'a>(
+    maps: impl Iterator<Item = &'a BTreeMap<String, f64>>,
+) -> BTreeMap<String, f64> {
+    let mut values_by_name: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for (name, value) in maps.flatten() {
+        values_by_name.entry(name.clone()).or_default().push(*value);
+    }
+    #[allow(clippy::cast_precision_loss)]
+    values_by_name
+        .into_iter()
+        .map(|(name, values)| (name, values.iter().sum::<f64>() / values.len() as f64))
+        .collect()
+}
+
 /// Handle result of process, depending on exit status.
 fn handle_command_result(result: Result<ExitStatus, std::io::Error>) {
     match result {
@@ -63,14 +331,82 @@ fn handle_command_result(result: Result<ExitStatus, std::io::Error>) {
     }
 }
 
-/// Record the run time of a validated command configuration.
+/// Read a pipe to the end on a separate thread, optionally echoing everything read.
+fn capture_pipe(
+    mut pipe: impl Read + Send + 'static,
+    mut echo: Option<Box<dyn Write + Send>>,
+) -> JoinHandle<std::io::Result<String>> {
+    std::thread::spawn(move || {
+        let mut captured = Vec::new();
+        let mut buffer = [0u8; 8192];
+        loop {
+            let read = pipe.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            if let Some(echo) = echo.as_mut() {
+                echo.write_all(&buffer[..read])?;
+            }
+            captured.extend_from_slice(&buffer[..read]);
+        }
+        Ok(String::from_utf8_lossy(&captured).into_owned())
+    })
+}
+
+/// Wait for a pipe capture to finish, if there is one.
+fn join_capture(capture: Option<JoinHandle<std::io::Result<String>>>) -> Result<Option<String>> {
+    capture
+        .map(|handle| {
+            handle
+                .join()
+                .map_err(|_| anyhow!("Output capture thread panicked"))?
+                .map_err(anyhow::Error::from)
+        })
+        .transpose()
+}
+
+/// Extract all custom metrics from the output of a finished program.
+/// A metric that cannot be extracted is missing from the run, with a warning.
+fn extract_metrics(
+    extractors: &[Extractor],
+    output: &CapturedOutput,
+    command: &Command<Validated>,
+) -> BTreeMap<String, f64> {
+    extractors
+        .iter()
+        .filter_map(|extractor| {
+            match extractor.extract(output, &command.working_dir) {
+                Ok(value) => Some((extractor.name.clone(), value)),
+                Err(err) => {
+                    // TODO: Change to proper logging (warning level)
+                    eprintln!(
+                        "Warning: {err:#} in {}, missing from this run",
+                        extractor.source()
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Record the run time of a validated command configuration,
+/// extracting custom metrics from its output.
 ///
 /// # Errors
 ///
 /// Surfaces any internal errors encountered while running the measured program.
-/// Note that the measured program failing is not an error.
-pub fn record_runtime(command: &Command<Validated>) -> Result<Results> {
-    let mut invocation = command.to_command();
+/// Note that neither the measured program failing nor a missing custom metric is an error.
+pub fn record_runtime(command: &Command<Validated>, extractors: &[Extractor]) -> Result<Results> {
+    let mut invocation = command.to_command()?;
+    let capture_stdout = extractors.iter().any(Extractor::reads_stdout);
+    let capture_stderr = extractors.iter().any(Extractor::reads_stderr);
+    if capture_stdout {
+        invocation.stdout(Stdio::piped());
+    }
+    if capture_stderr {
+        invocation.stderr(Stdio::piped());
+    }
 
     let mut probe = System::new_with_specifics(RefreshKind::nothing().with_processes(*CPU_AND_MEM));
     // TODO: Make this value configurable, with a warning on too short interval.
@@ -86,37 +422,115 @@ pub fn record_runtime(command: &Command<Validated>) -> Result<Results> {
     let initial_capacity = 5_000 / (u32::try_from(polling_interval.as_millis())? * probing_period);
     let mut probe_results: Vec<ProbeMeasurement> = Vec::with_capacity(initial_capacity as usize);
 
+    let cgroup = command.cgroup.as_ref().map(Cgroup::create).transpose()?;
+    if let Some(cgroup) = &cgroup {
+        cgroup.attach(&mut invocation)?;
+    }
+
+    let cpu_governor = command.scheduling.cpu_governor();
+    let counters = command.counters.then(Counters::start);
     let timer = Instant::now();
 
-    let mut handle = invocation.spawn()?;
+    let mut handle = signals::spawn(&mut invocation)?;
+    let stdout_capture = handle.stdout.take().map(|pipe| {
+        let echo: Option<Box<dyn Write + Send>> = command
+            .show_output
+            .then(|| Box::new(std::io::stdout()) as _);
+        capture_pipe(pipe, echo)
+    });
+    let stderr_capture = handle
+        .stderr
+        .take()
+        .map(|pipe| capture_pipe(pipe, Some(Box::new(std::io::stderr()))));
     let pid = Pid::from_u32(handle.id());
     let pid_binding = [pid];
     let probe_update = ProcessesToUpdate::Some(&pid_binding);
 
     let mut index = 0u32;
     loop {
-        match handle.try_wait().transpose() {
-            // Process has finished
-            Some(result) => {
-                let wall_time = timer.elapsed();
-                handle_command_result(result);
-                return Ok(Results::from_measurements(wall_time, probe_results));
-            }
-            // Process is still running
-            None => {
-                if index % probing_period == 0 {
-                    probe.refresh_processes_specifics(probe_update, true, *CPU_AND_MEM);
-                    let process = probe
-                        .process(pid)
-                        .ok_or_else(|| anyhow!(format!("Process {pid} not found")))?;
-                    probe_results.push(ProbeMeasurement {
-                        cpu: process.cpu_usage(),
-                        ram: process.memory(),
-                    });
-                }
-            }
+        // Process has finished
+        if io_accounting::has_exited(handle.id())? {
+            let wall_time = timer.elapsed();
+            // Read before reaping the process, which discards its statistics.
+            let io = io_accounting::read(handle.id());
+            handle_command_result(handle.wait());
+            signals::check()?;
+            let output = CapturedOutput {
+                stdout: join_capture(stdout_capture)?,
+                stderr: join_capture(stderr_capture)?,
+            };
+            let metrics = extract_metrics(extractors, &output, command);
+            let (counters, counter_sources) = counters.map(Counters::read).unwrap_or_default();
+            return Ok(Results {
+                cpu_governor,
+                counters,
+                counter_sources,
+                io,
+                // Dropping the cgroup kills processes left behind.
+                cgroup: cgroup.map(|cgroup| cgroup.stats()).unwrap_or_default(),
+                ..Results::from_measurements(wall_time, probe_results, metrics)
+            });
+        }
+        // Process is still running
+        if index.is_multiple_of(probing_period) {
+            probe.refresh_processes_specifics(probe_update, true, *CPU_AND_MEM);
+            let process = probe
+                .process(pid)
+                .ok_or_else(|| anyhow!(format!("Process {pid} not found")))?;
+            probe_results.push(ProbeMeasurement {
+                cpu: process.cpu_usage(),
+                ram: process.memory(),
+            });
         }
         index += 1;
         sleep(polling_interval);
     }
 }
+
+/// Run a benchmark the configured number of times.
+/// Its `setup` and `teardown` hooks run once per commit, see [`crate::runner`].
+///
+/// # Errors
+///
+/// Surfaces any internal errors encountered while running the benchmark.
+pub fn measure_benchmark(benchmark: &Benchmark) -> Result<Summary> {
+    let runs = (0..benchmark.runs)
+        .map(|_| measure_run(&benchmark.command, &benchmark.hooks, &benchmark.metrics))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Summary::from_runs(runs))
+}
+
+/// Measure a single run of a command, running the `prepare` and `cleanup` hooks
+/// before and after it. Hooks are not included in the results.
+///
+/// In cold mode, filesystem caches are dropped right before the run,
+/// and the results record whether that succeeded.
+///
+/// # Errors
+///
+/// Returns an error if a hook fails or the run could not be measured.
+pub fn measure_run(
+    command: &Command<Validated>,
+    hooks: &Hooks,
+    extractors: &[Extractor],
+) -> Result<Results> {
+    hooks.run(Hook::Prepare)?;
+    let caches_dropped = hooks.drop_caches.is_some().then(|| {
+        hooks.run(Hook::DropCaches).map_or_else(
+            |err| {
+                eprintln!("Warning: Failed to drop caches: {err}");
+                false
+            },
+            |()| true,
+        )
+    });
+    let results = record_runtime(command, extractors).map(|results| Results {
+        caches_dropped,
+        ..results
+    });
+    // Clean up even if the run failed, reporting the first error.
+    let cleanup = hooks.run(Hook::Cleanup);
+    let results = results?;
+    cleanup?;
+    Ok(results)
+}
@@ -0,0 +1,308 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Where the value of a metric is read from.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// Standard output of the measured program.
+    #[default]
+    Stdout,
+    /// Standard error of the measured program.
+    Stderr,
+    /// A file written by the measured program, relative to its working directory.
+    File(PathBuf),
+}
+
+/// A custom metric as defined in the configuration.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Definition {
+    /// Name of the metric, used to access it in templates.
+    pub name: String,
+    /// Where to read the metric from.
+    #[serde(default)]
+    pub source: Source,
+    /// Regular expression matching the value.
+    /// Uses the capture group named `value`, the first capture group
+    /// or the entire match, in that order.
+    pub regex: Option<String>,
+    /// Path to the value in JSON output, e.g. `results.latency.p99` or `runs[0].ops`.
+    pub json_path: Option<String>,
+    /// Whether higher values are better, e.g. for throughput.
+    /// Default is false
+    #[serde(default)]
+    pub higher_is_better: bool,
+    /// Largest tolerated change for the worse in percent, e.g. `5.0`.
+    /// Comparing fails if head is worse than base by more
+    pub threshold: Option<f64>,
+}
+
+/// How to find the value of a metric in the program output.
+#[derive(Debug)]
+enum Pattern {
+    /// Match the value with a regular expression.
+    Regex(Regex),
+    /// Look up the value in a JSON document.
+    JsonPath(Vec<String>),
+}
+
+/// A validated metric definition, ready to extract values.
+#[derive(Debug)]
+pub struct Extractor {
+    /// Name of the metric.
+    pub name: String,
+    /// Whether higher values are better.
+    pub higher_is_better: bool,
+    /// Largest tolerated change for the worse in percent, if any.
+    pub threshold: Option<f64>,
+    /// Where to read the metric from.
+    source: Source,
+    /// How to find the value.
+    pattern: Pattern,
+}
+
+/// Output captured from a measured program.
+#[derive(Default)]
+pub struct CapturedOutput {
+    /// Standard output, if captured.
+    pub stdout: Option<String>,
+    /// Standard error, if captured.
+    pub stderr: Option<String>,
+}
+
+impl TryFrom<Definition> for Extractor {
+    type Error = anyhow::Error;
+
+    fn try_from(definition: Definition) -> Result<Self> {
+        let Definition {
+            name,
+            source,
+            regex,
+            json_path,
+            higher_is_better,
+            threshold,
+        } = definition;
+        if threshold.is_some_and(|threshold| !(threshold.is_finite() && threshold >= 0.0)) {
+            return Err(anyhow!(
+                "Threshold of metric `{name}` must be a non-negative number"
+            ));
+        }
+        let pattern = match (regex, json_path) {
+            (Some(regex), None) => Pattern::Regex(
+                Regex::new(&regex).with_context(|| format!("Invalid regex for metric `{name}`"))?,
+            ),
+            (None, Some(path)) => Pattern::JsonPath(parse_json_path(&path)),
+            _ => {
+                return Err(anyhow!(
+                    "Metric `{name}` must specify exactly one of `regex` and `json_path`"
+                ))
+            }
+        };
+        Ok(Self {
+            name,
+            higher_is_better,
+            threshold,
+            source,
+            pattern,
+        })
+    }
+}
+
+/// Split a JSON path such as `$.runs[0].ops` into its keys: `["runs", "0", "ops"]`.
+fn parse_json_path(path: &str) -> Vec<String> {
+    path.trim_start_matches('$')
+        .replace('[', ".")
+        .replace(']', "")
+        .split('.')
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Look up a value in a JSON document by its keys.
+/// Numeric keys index into arrays.
+fn lookup<'a>(value: &'a serde_json::Value, keys: &[String]) -> Option<&'a serde_json::Value> {
+    keys.iter().try_fold(value, |value, key| match value {
+        serde_json::Value::Array(array) => array.get(key.parse::<usize>().ok()?),
+        _ => value.get(key),
+    })
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Stderr => write!(f, "stderr"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Extractor {
+    /// Where the metric is read from.
+    #[must_use]
+    pub const fn source(&self) -> &Source {
+        &self.source
+    }
+
+    /// Change for the worse in percent from `base` to `head`,
+    /// if it exceeds the threshold of the metric.
+    #[must_use]
+    pub fn exceeded_threshold(&self, base: f64, head: f64) -> Option<f64> {
+        let threshold = self.threshold?;
+        if base == 0.0 {
+            return None;
+        }
+        let change = (head - base) / base.abs() * 100.0;
+        let worse = if self.higher_is_better {
+            -change
+        } else {
+            change
+        };
+        (worse > threshold).then_some(worse)
+    }
+
+    /// Whether the metric is read from standard output.
+    #[must_use]
+    pub const fn reads_stdout(&self) -> bool {
+        matches!(self.source, Source::Stdout)
+    }
+
+    /// Whether the metric is read from standard error.
+    #[must_use]
+    pub const fn reads_stderr(&self) -> bool {
+        matches!(self.source, Source::Stderr)
+    }
+
+    /// Extract the metric value from the output of a finished program.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source could not be read
+    /// or does not contain a numeric value matching the pattern.
+    pub fn extract(&self, output: &CapturedOutput, working_dir: &Path) -> Result<f64> {
+        let name = &self.name;
+        let text = match &self.source {
+            Source::Stdout => output.stdout.clone().unwrap_or_default(),
+            Source::Stderr => output.stderr.clone().unwrap_or_default(),
+            Source::File(path) => {
+                let path = working_dir.join(path);
+                std::fs::read_to_string(&path).with_context(|| {
+                    format!("Failed to read {} for metric `{name}`", path.display())
+                })?
+            }
+        };
+        match &self.pattern {
+            Pattern::Regex(regex) => {
+                let captures = regex
+                    .captures(&text)
+                    .ok_or_else(|| anyhow!("No match found for metric `{name}`"))?;
+                let value = captures
+                    .name("value")
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0))
+                    .map_or("", |value| value.as_str());
+                value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Value {value:?} of metric `{name}` is not a number"))
+            }
+            Pattern::JsonPath(keys) => {
+                let document: serde_json::Value = serde_json::from_str(&text)
+                    .with_context(|| format!("Output for metric `{name}` is not valid JSON"))?;
+                let value = lookup(&document, keys)
+                    .ok_or_else(|| anyhow!("No value found for metric `{name}`"))?;
+                match value {
+                    serde_json::Value::Number(number) => number.as_f64(),
+                    serde_json::Value::String(string) => string.trim().parse().ok(),
+                    _ => None,
+                }
+                .ok_or_else(|| anyhow!("Value {value} of metric `{name}` is not a number"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CapturedOutput, Definition, Extractor, Source};
+    use std::path::Path;
+
+    fn extractor(regex: Option<&str>, json_path: Option<&str>) -> Extractor {
+        Extractor::try_from(Definition {
+            name: "metric".to_string(),
+            source: Source::Stdout,
+            regex: regex.map(str::to_string),
+            json_path: json_path.map(str::to_string),
+            higher_is_better: false,
+            threshold: None,
+        })
+        .unwrap()
+    }
+
+    fn extract(extractor: &Extractor, stdout: &str) -> anyhow::Result<f64> {
+        let output = CapturedOutput {
+            stdout: Some(stdout.to_string()),
+            stderr: None,
+        };
+        extractor.extract(&output, Path::new("."))
+    }
+
+    #[test]
+    fn regex() {
+        let output = "throughput: 1234.5 ops/s\nlatency: 3 ms";
+        let first_group = extractor(Some(r"throughput: ([\d.]+)"), None);
+        assert!((extract(&first_group, output).unwrap() - 1234.5).abs() < f64::EPSILON);
+        let named_group = extractor(Some(r"(\w+): (?<value>\d+) ms"), None);
+        assert!((extract(&named_group, output).unwrap() - 3.0).abs() < f64::EPSILON);
+        let whole_match = extractor(Some(r"\d+$"), None);
+        assert!(extract(&whole_match, "42").is_ok());
+        assert!(extract(&first_group, "no numbers here").is_err());
+    }
+
+    #[test]
+    fn json_path() {
+        let output = r#"{"results": {"runs": [{"ops": 10}, {"ops": "20.5"}]}}"#;
+        let number = extractor(None, Some("$.results.runs[0].ops"));
+        assert!((extract(&number, output).unwrap() - 10.0).abs() < f64::EPSILON);
+        let string = extractor(None, Some("results.runs.1.ops"));
+        assert!((extract(&string, output).unwrap() - 20.5).abs() < f64::EPSILON);
+        let missing = extractor(None, Some("results.runs[2].ops"));
+        assert!(extract(&missing, output).is_err());
+    }
+
+    #[test]
+    fn requires_one_pattern() {
+        let definition = |regex: Option<&str>, json_path: Option<&str>| Definition {
+            name: "metric".to_string(),
+            source: Source::Stdout,
+            regex: regex.map(str::to_string),
+            json_path: json_path.map(str::to_string),
+            higher_is_better: false,
+            threshold: None,
+        };
+        assert!(Extractor::try_from(definition(None, None)).is_err());
+        assert!(Extractor::try_from(definition(Some("x"), Some("x"))).is_err());
+        assert!(Extractor::try_from(definition(Some("("), None)).is_err());
+    }
+
+    #[test]
+    fn threshold() {
+        let metric = |higher_is_better| Extractor {
+            higher_is_better,
+            threshold: Some(5.0),
+            ..extractor(Some("x"), None)
+        };
+        assert_eq!(metric(false).exceeded_threshold(100.0, 104.0), None);
+        assert!(metric(false).exceeded_threshold(100.0, 110.0).is_some());
+        assert_eq!(metric(false).exceeded_threshold(100.0, 80.0), None);
+        assert!(metric(true).exceeded_threshold(100.0, 80.0).is_some());
+        assert_eq!(metric(true).exceeded_threshold(100.0, 110.0), None);
+        assert_eq!(
+            extractor(Some("x"), None).exceeded_threshold(1.0, 2.0),
+            None
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::measurement::Results;
+
+/// How runs are detected as outliers.
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    /// Keep all runs.
+    #[default]
+    None,
+    /// Reject values outside the fences `Q1 - k * IQR` and `Q3 + k * IQR`,
+    /// where `k` is the threshold. Default threshold is 1.5
+    Iqr,
+    /// Reject values whose modified z-score, based on the median absolute deviation,
+    /// exceeds the threshold. Default threshold is 3.5
+    Mad,
+}
+
+impl Method {
+    /// Threshold used if none is configured.
+    #[must_use]
+    pub const fn default_threshold(self) -> f64 {
+        match self {
+            Self::None => 0.0,
+            Self::Iqr => 1.5,
+            Self::Mad => 3.5,
+        }
+    }
+
+    /// Which of the values are outliers.
+    /// Too few values to tell contain no outliers, and neither do values
+    /// that mostly do not vary, where any difference would be an outlier.
+    #[must_use]
+    pub fn detect(self, values: &[f64], threshold: f64) -> Vec<bool> {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let fences = match self {
+            Self::Iqr if values.len() >= 4 => {
+                let (q1, q3) = (quantile(&sorted, 0.25), quantile(&sorted, 0.75));
+                let margin = threshold * (q3 - q1);
+                (margin > 0.0).then_some((q1 - margin, q3 + margin))
+            }
+            Self::Mad if values.len() >= 3 => {
+                let median = quantile(&sorted, 0.5);
+                let mut deviations: Vec<f64> =
+                    values.iter().map(|value| (value - median).abs()).collect();
+                deviations.sort_by(f64::total_cmp);
+                // The modified z-score is `0.6745 * (value - median) / MAD`.
+                let margin = threshold * quantile(&deviations, 0.5) / 0.6745;
+                (margin > 0.0).then_some((median - margin, median + margin))
+            }
+            // Too few values, or keeping all runs.
+            Self::Iqr | Self::Mad | Self::None => None,
+        };
+        values
+            .iter()
+            .map(|value| fences.is_some_and(|(low, high)| *value < low || *value > high))
+            .collect()
+    }
+}
+
+/// Quantile of sorted values, interpolating linearly between neighbours.
+fn quantile(sorted: &[f64], fraction: f64) -> f64 {
+    #[allow(clippy::cast_precision_loss)]
+    let position = fraction * (sorted.len() - 1) as f64;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let lower = position.floor() as usize;
+    let upper = (lower + 1).min(sorted.len() - 1);
+    #[allow(clippy::cast_precision_loss)]
+    let weight = position - lower as f64;
+    (sorted[upper] - sorted[lower]).mul_add(weight, sorted[lower])
+}
+
+/// Configured outlier handling.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Outliers {
+    /// How outliers are detected.
+    pub method: Method,
+    /// Threshold of the method, see [`Method`].
+    pub threshold: f64,
+    /// Fraction of rejected runs above which a warning is shown.
+    pub max_fraction: f64,
+}
+
+impl Outliers {
+    /// Indices of the runs that are outliers in wall time or any custom metric.
+    #[must_use]
+    pub fn rejected(&self, runs: &[Results]) -> Vec<usize> {
+        let mut series: Vec<Vec<f64>> =
+            vec![runs.iter().map(|run| run.wall_time.as_secs_f64()).collect()];
+        // Metrics missing in some runs cannot be compared across runs.
+        if let Some(first) = runs.first() {
+            series.extend(first.metrics.keys().filter_map(|name| {
+                runs.iter()
+                    .map(|run| run.metrics.get(name).copied())
+                    .collect::<Option<Vec<f64>>>()
+            }));
+        }
+        let flags: Vec<Vec<bool>> = series
+            .iter()
+            .map(|values| self.method.detect(values, self.threshold))
+            .collect();
+        (0..runs.len())
+            .filter(|index| flags.iter().any(|flags| flags[*index]))
+            .collect()
+    }
+
+    /// Whether so many runs were rejected that the results are questionable.
+    #[must_use]
+    pub fn too_many(&self, rejected: usize, runs: usize) -> bool {
+        #[allow(clippy::cast_precision_loss)]
+        let fraction = rejected as f64 / runs.max(1) as f64;
+        fraction > self.max_fraction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Method;
+
+    const VALUES: [f64; 8] = [10.0, 10.2, 9.9, 10.1, 10.0, 9.8, 10.3, 25.0];
+
+    #[test]
+    fn iqr() {
+        let flags = Method::Iqr.detect(&VALUES, 1.5);
+        assert_eq!(flags.iter().filter(|flag| **flag).count(), 1);
+        assert!(flags[7]);
+        assert!(!Method::Iqr.detect(&VALUES[5..], 1.5).contains(&true));
+    }
+
+    #[test]
+    fn mad() {
+        let flags = Method::Mad.detect(&VALUES, 3.5);
+        assert_eq!(flags.iter().filter(|flag| **flag).count(), 1);
+        assert!(flags[7]);
+        assert!(!Method::Mad
+            .detect(&[1.0, 1.0, 1.0, 2.0], 3.5)
+            .contains(&true));
+    }
+
+    #[test]
+    fn none() {
+        assert!(!Method::None.detect(&VALUES, 0.0).contains(&true));
+    }
+}
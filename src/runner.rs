@@ -0,0 +1,618 @@
+use std::collections::BTreeSet;
+use std::panic::catch_unwind;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::adaptive::Adaptive;
+use crate::config::{Benchmark, Command, ExecutionContext, Hook, Validated, PERFORMANCE_GOVERNOR};
+use crate::git::{DiffTargets, OriginalHead, SNAPSHOT_REF};
+use crate::measurement::{
+    measure_benchmark, measure_run, BenchmarkComparison, BenchmarkResults, Comparison, RefResults,
+    Results, Summary,
+};
+use crate::outliers::Outliers;
+use crate::schedule::{Schedule, Side};
+use crate::signals;
+use git2::Oid;
+
+/// Results of base and head of each benchmark.
+type SummaryPairs = Vec<(Summary, Summary)>;
+
+/// Measure all benchmarks on base and head, in the order given by the configured schedule.
+///
+/// # Errors
+///
+/// Returns an error if checking out, building or measuring fails.
+pub fn compare(execution_context: &ExecutionContext) -> Result<Comparison> {
+    let DiffTargets { base_ref, head_ref } = &execution_context.git_targets;
+    warn_about_cpu_governors(&execution_context.benchmarks);
+    let paths = &execution_context.paths;
+    let head_reused = !paths.is_empty()
+        && !execution_context
+            .git_ctx
+            .changes_in(*base_ref, *head_ref, paths)?;
+    let (results, base_cached, head_cached) = if head_reused {
+        println!("No changes in paths, reusing the results of {base_ref} for {head_ref}");
+        let (results, cached) = run_base_only(execution_context)?;
+        (results, cached, cached)
+    } else if execution_context.schedule.is_interleaved() {
+        run_interleaved(execution_context)?
+    } else {
+        run_sequential(execution_context)?
+    };
+    let outliers = &execution_context.outliers;
+
+    Ok(Comparison {
+        base_ref: base_ref.to_string(),
+        head_ref: head_ref.to_string(),
+        benchmarks: execution_context
+            .benchmarks
+            .iter()
+            .zip(results)
+            .map(|(benchmark, (base, head))| {
+                let adaptive = execution_context
+                    .adaptive
+                    .map(|adaptive| adaptive.outcome(&base.wall_times(), &head.wall_times()));
+                let (base, head) = (
+                    base.reject_outliers(outliers),
+                    head.reject_outliers(outliers),
+                );
+                warn_about_outliers(&benchmark.name, outliers, [&base, &head]);
+                BenchmarkComparison {
+                    name: benchmark.name.clone(),
+                    group: benchmark.group.clone(),
+                    params: benchmark.params.clone(),
+                    base,
+                    head,
+                    adaptive,
+                }
+            })
+            .collect(),
+        head_reused,
+        base_cached,
+        head_cached,
+    })
+}
+
+/// Compare the working tree, including uncommitted changes and untracked files,
+/// with the base reference.
+///
+/// Saves the working tree and index in a snapshot commit, which is measured as head
+/// after cleaning the working tree, then restores them.
+///
+/// # Errors
+///
+/// Returns an error if the snapshot cannot be taken or restored,
+/// or if checking out, building or measuring fails.
+pub fn compare_working_tree(execution_context: &mut ExecutionContext) -> Result<Comparison> {
+    let snapshot = execution_context.git_ctx.snapshot()?;
+    execution_context.git_targets.head_ref = snapshot.commit;
+    let comparison = compare(execution_context);
+    execution_context
+        .git_ctx
+        .restore(&snapshot)
+        .with_context(|| {
+            format!(
+                "Failed to restore the working tree, \
+                 its snapshot is commit {} referenced by {SNAPSHOT_REF}",
+                snapshot.commit
+            )
+        })?;
+    if let Some(signal) = signals::received() {
+        eprintln!("Interrupted by {signal}, restored uncommitted changes and untracked files");
+    }
+    let mut comparison = comparison?;
+    comparison.head_ref = format!("working tree ({})", comparison.head_ref);
+    Ok(comparison)
+}
+
+/// Measure all benchmarks on the head reference only.
+///
+/// # Errors
+///
+/// Returns an error if checking out, building or measuring fails.
+pub fn measure(execution_context: &ExecutionContext) -> Result<RefResults> {
+    let head_ref = execution_context.git_targets.head_ref.to_string();
+    warn_about_cpu_governors(&execution_context.benchmarks);
+    let original_head = execution_context.git_ctx.original_head()?;
+
+    println!("Measuring {head_ref}...");
+    let (summaries, cached) = run_cached(
+        execution_context,
+        execution_context.git_targets.head_ref,
+        &original_head,
+    )?;
+    Ok(RefResults {
+        cached,
+        ..ref_results(execution_context, head_ref, summaries)
+    })
+}
+
+/// Measure all benchmarks on the working tree as it is, including uncommitted changes,
+/// without checking out anything.
+///
+/// # Errors
+///
+/// Returns an error if building or measuring fails.
+pub fn measure_working_tree(execution_context: &ExecutionContext) -> Result<RefResults> {
+    let git_ref = format!("working tree at {}", execution_context.git_targets.head_ref);
+    warn_about_cpu_governors(&execution_context.benchmarks);
+
+    println!("Measuring {git_ref}...");
+    let summaries = build_and_measure(
+        execution_context.build_command.as_ref(),
+        &execution_context.benchmarks,
+    )?;
+    Ok(ref_results(execution_context, git_ref, summaries))
+}
+
+/// Results of measuring a single reference, numbering the runs and rejecting outliers.
+fn ref_results(
+    execution_context: &ExecutionContext,
+    git_ref: String,
+    mut summaries: Vec<Summary>,
+) -> RefResults {
+    for summary in &mut summaries {
+        for (order, run) in summary.runs.iter_mut().enumerate() {
+            run.order = order;
+        }
+    }
+
+    let outliers = &execution_context.outliers;
+    RefResults {
+        git_ref,
+        benchmarks: execution_context
+            .benchmarks
+            .iter()
+            .zip(summaries)
+            .map(|(benchmark, summary)| {
+                let summary = summary.reject_outliers(outliers);
+                warn_about_outliers(&benchmark.name, outliers, [&summary]);
+                BenchmarkResults {
+                    name: benchmark.name.clone(),
+                    group: benchmark.group.clone(),
+                    params: benchmark.params.clone(),
+                    summary,
+                }
+            })
+            .collect(),
+        cached: false,
+    }
+}
+
+/// Warn if the CPUs running the benchmarks may change their frequency during measurements.
+fn warn_about_cpu_governors(benchmarks: &[Benchmark]) {
+    let governors: BTreeSet<String> = benchmarks
+        .iter()
+        .filter_map(|benchmark| benchmark.command.scheduling.cpu_governor())
+        .collect();
+    for governor in governors
+        .iter()
+        .filter(|governor| governor.split(',').any(|name| name != PERFORMANCE_GOVERNOR))
+    {
+        eprintln!(
+            "Warning: CPU frequency governor is `{governor}` instead of `{PERFORMANCE_GOVERNOR}`, \
+             measurements may be noisy."
+        );
+    }
+}
+
+/// Warn if a large fraction of the runs of a benchmark were rejected as outliers.
+fn warn_about_outliers<const N: usize>(name: &str, outliers: &Outliers, summaries: [&Summary; N]) {
+    let rejected = summaries.iter().map(|summary| summary.rejected.len()).sum();
+    let runs = summaries.iter().map(|summary| summary.runs.len()).sum();
+    if outliers.too_many(rejected, runs) {
+        eprintln!(
+            "Warning: {rejected} of {runs} runs of `{name}` were rejected as outliers, \
+             measurements may be unreliable."
+        );
+    }
+}
+
+/// Run a build command, failing if it does not succeed.
+fn build(command: &Command<Validated>) -> Result<()> {
+    let status = signals::status(&mut command.to_command()?)?;
+    if !status.success() {
+        return Err(anyhow!("Build command failed with {status}"));
+    }
+    Ok(())
+}
+
+/// Safely run the measurements of all benchmarks, restoring the git repo on failure.
+fn run_safely(
+    execution_context: &ExecutionContext,
+    git_ref: &String,
+    original_head: &OriginalHead,
+) -> Result<Vec<Summary>> {
+    let ExecutionContext {
+        git_ctx,
+        build_command,
+        benchmarks,
+        ..
+    } = execution_context;
+    let program_result = catch_unwind(|| {
+        git_ctx.checkout(git_ref)?;
+        build_and_measure(build_command.as_ref(), benchmarks)
+    });
+
+    // Restore repository to previous state regardless of execution status.
+    // Failing to do so is returned rather than panicking, so that callers can still
+    // restore what they changed themselves, e.g. the working tree.
+    git_ctx.restore_head(original_head).with_context(|| {
+        format!(
+            "Failed to restore HEAD to {original_head} after measuring, please inspect manually"
+        )
+    })?;
+    if let Some(signal) = signals::received() {
+        eprintln!("Interrupted by {signal}, restored HEAD to {original_head}");
+    }
+
+    program_result.map_err(|_| anyhow!("Internal failure!"))?
+}
+
+/// Build the checked out files if configured, then measure all benchmarks
+/// between the `setup` and `teardown` hooks.
+fn build_and_measure(
+    build_command: Option<&Command<Validated>>,
+    benchmarks: &[Benchmark],
+) -> Result<Vec<Summary>> {
+    if let Some(build_command) = build_command {
+        build(build_command)?;
+    }
+    CommitHooks::around(&[CommitHooks::new(benchmarks)], || {
+        benchmarks.iter().map(measure_benchmark).collect()
+    })
+}
+
+/// Distinct `setup` and `teardown` hooks of all benchmarks, run once per commit,
+/// before the first and after the last run of any benchmark.
+struct CommitHooks {
+    /// Commands of the `setup` hooks, in the order of the benchmarks.
+    setup: Vec<Command<Validated>>,
+    /// Commands of the `teardown` hooks, in the order of the benchmarks.
+    teardown: Vec<Command<Validated>>,
+}
+
+impl CommitHooks {
+    /// Hooks of the benchmarks, running each distinct command once.
+    fn new(benchmarks: &[Benchmark]) -> Self {
+        let distinct = |hook: Hook| {
+            let mut seen = BTreeSet::new();
+            benchmarks
+                .iter()
+                .filter_map(|benchmark| benchmark.hooks.get(hook))
+                .filter(|command| seen.insert((&command.args, &command.working_dir, &command.env)))
+                .cloned()
+                .collect()
+        };
+        Self {
+            setup: distinct(Hook::Setup),
+            teardown: distinct(Hook::Teardown),
+        }
+    }
+
+    /// Copy of the hooks with their working directories relocated,
+    /// see [`Command::relocate`].
+    fn relocate(&self, from: &Path, to: &Path) -> Self {
+        let relocate = |commands: &[Command<Validated>]| {
+            commands
+                .iter()
+                .map(|command| command.relocate(from, to))
+                .collect()
+        };
+        Self {
+            setup: relocate(&self.setup),
+            teardown: relocate(&self.teardown),
+        }
+    }
+
+    /// Run `measure` between the `setup` and `teardown` hooks of each side.
+    /// Tears down every side whose setup succeeded, even if measuring failed,
+    /// reporting the first error.
+    fn around<T>(sides: &[Self], measure: impl FnOnce() -> Result<T>) -> Result<T> {
+        let mut set_up = 0;
+        let mut result = sides
+            .iter()
+            .try_for_each(|hooks| {
+                hooks
+                    .setup
+                    .iter()
+                    .try_for_each(|command| Hook::Setup.run(command))?;
+                set_up += 1;
+                Ok(())
+            })
+            .and_then(|()| measure());
+        for hooks in sides[..set_up].iter().rev() {
+            let teardown = hooks
+                .teardown
+                .iter()
+                .try_for_each(|command| Hook::Teardown.run(command));
+            if let (Ok(_), Err(err)) = (&result, teardown) {
+                result = Err(err);
+            }
+        }
+        result
+    }
+}
+
+/// Measure base only, using its results for head as well.
+/// Also returns whether the results were cached.
+fn run_base_only(execution_context: &ExecutionContext) -> Result<(SummaryPairs, bool)> {
+    let base_ref = execution_context.git_targets.base_ref;
+    let original_head = execution_context.git_ctx.original_head()?;
+
+    println!("Measuring {base_ref}...");
+    let (results, cached) = run_cached(execution_context, base_ref, &original_head)?;
+    let results = results
+        .into_iter()
+        .map(|mut summary| {
+            for (order, run) in summary.runs.iter_mut().enumerate() {
+                run.order = order;
+            }
+            (summary.clone(), summary)
+        })
+        .collect();
+    Ok((results, cached))
+}
+
+/// Tree of a reference, if caching is enabled, with the results cached for it, if any.
+fn cache_lookup(
+    execution_context: &ExecutionContext,
+    git_ref: Oid,
+) -> Result<Option<(Oid, Option<Vec<Summary>>)>> {
+    let Some(cache) = &execution_context.cache else {
+        return Ok(None);
+    };
+    let tree = execution_context
+        .git_ctx
+        .repo
+        .find_commit(git_ref)?
+        .tree_id();
+    let summaries = cache.load(tree);
+    if summaries.is_some() {
+        println!("Reusing cached results of identical tree {tree} for {git_ref}");
+    }
+    Ok(Some((tree, summaries)))
+}
+
+/// Store results measured on a tree looked up with [`cache_lookup`], unless they were cached.
+fn cache_store(
+    execution_context: &ExecutionContext,
+    lookup: Option<&(Oid, Option<Vec<Summary>>)>,
+    summaries: &[Summary],
+) -> Result<()> {
+    match (&execution_context.cache, lookup) {
+        (Some(cache), Some((tree, None))) => cache.store(*tree, summaries),
+        _ => Ok(()),
+    }
+}
+
+/// Measure a reference like [`run_safely`], unless results of its tree are cached.
+/// Also returns whether the results were cached.
+fn run_cached(
+    execution_context: &ExecutionContext,
+    git_ref: Oid,
+    original_head: &OriginalHead,
+) -> Result<(Vec<Summary>, bool)> {
+    let lookup = cache_lookup(execution_context, git_ref)?;
+    if let Some((_, Some(summaries))) = lookup {
+        return Ok((summaries, true));
+    }
+    let summaries = run_safely(execution_context, &git_ref.to_string(), original_head)?;
+    cache_store(execution_context, lookup.as_ref(), &summaries)?;
+    Ok((summaries, false))
+}
+
+/// Measure all runs of base, then all runs of head, checking out each in the repository.
+/// Also returns whether the results of base and head were cached.
+fn run_sequential(execution_context: &ExecutionContext) -> Result<(SummaryPairs, bool, bool)> {
+    let ExecutionContext {
+        git_targets: DiffTargets { base_ref, head_ref },
+        ..
+    } = execution_context;
+
+    let original_head = execution_context.git_ctx.original_head()?;
+
+    println!("Measuring {base_ref}...");
+    let (mut base_results, base_cached) = run_cached(execution_context, *base_ref, &original_head)?;
+
+    println!("Measuring {head_ref}...");
+    let (mut head_results, head_cached) = run_cached(execution_context, *head_ref, &original_head)?;
+
+    // Number the runs in the order they were executed.
+    let mut order = 0;
+    for summary in base_results.iter_mut().chain(&mut head_results) {
+        for run in &mut summary.runs {
+            run.order = order;
+            order += 1;
+        }
+    }
+    Ok((
+        base_results.into_iter().zip(head_results).collect(),
+        base_cached,
+        head_cached,
+    ))
+}
+
+/// Separate copies of base and head, built once and measured in turns.
+struct BuildDirs {
+    /// Root directory of the repository working tree.
+    workdir: PathBuf,
+    /// Directory containing both copies.
+    root: PathBuf,
+}
+
+impl BuildDirs {
+    /// Directory holding the copy of one side.
+    fn dir(&self, side: Side) -> PathBuf {
+        self.root.join(side.name())
+    }
+
+    /// A command running in the copy of one side instead of the repository.
+    fn relocate(&self, command: &Command<Validated>, side: Side) -> Command<Validated> {
+        command.relocate(&self.workdir, &self.dir(side))
+    }
+}
+
+impl Drop for BuildDirs {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.root) {
+            eprintln!(
+                "Failed to remove build directory {}: {err}",
+                self.root.display()
+            );
+        }
+    }
+}
+
+/// Measure base and head in separate build directories, interleaving their runs.
+/// A side with cached results is neither built nor run, and only the other side is measured.
+/// Also returns whether the results of base and head were cached.
+fn run_interleaved(execution_context: &ExecutionContext) -> Result<(SummaryPairs, bool, bool)> {
+    let ExecutionContext {
+        git_ctx,
+        git_targets: DiffTargets { base_ref, head_ref },
+        build_command,
+        benchmarks,
+        schedule,
+        adaptive,
+        ..
+    } = execution_context;
+
+    let lookups = [
+        cache_lookup(execution_context, *base_ref)?,
+        cache_lookup(execution_context, *head_ref)?,
+    ];
+    let cached = lookups.each_ref().map(|lookup| {
+        lookup
+            .as_ref()
+            .and_then(|(_, summaries)| summaries.as_ref())
+    });
+    if let [Some(base), Some(head)] = cached {
+        return Ok((
+            base.iter().cloned().zip(head.iter().cloned()).collect(),
+            true,
+            true,
+        ));
+    }
+    let measured: Vec<Side> = [Side::Base, Side::Head]
+        .into_iter()
+        .filter(|side| cached[side.index()].is_none())
+        .collect();
+
+    let workdir = git_ctx
+        .repo
+        .workdir()
+        .ok_or_else(|| anyhow!("Interleaved schedules require a repository with a working tree"))?
+        .canonicalize()?;
+    let dirs = BuildDirs {
+        workdir,
+        root: git_ctx.scratch_dir().join("build"),
+    };
+    for side in &measured {
+        let git_ref = [base_ref, head_ref][side.index()];
+        let dir = dirs.dir(*side);
+        println!("Preparing {git_ref} in {}...", dir.display());
+        git_ctx
+            .export(git_ref.to_string(), &dir)
+            .with_context(|| format!("Failed to prepare build directory for {git_ref}"))?;
+        if let Some(build_command) = build_command {
+            build(&dirs.relocate(build_command, *side))?;
+        }
+    }
+
+    let hooks = CommitHooks::new(benchmarks);
+    let sides: Vec<CommitHooks> = measured
+        .iter()
+        .map(|side| hooks.relocate(&dirs.workdir, &dirs.dir(*side)))
+        .collect();
+    let mut order = 0;
+    let results: SummaryPairs = CommitHooks::around(&sides, || {
+        benchmarks
+            .iter()
+            .enumerate()
+            .map(|(index, benchmark)| {
+                println!("Measuring {}...", benchmark.name);
+                let cached = cached.map(|summaries| summaries.map(|summaries| &summaries[index]));
+                measure_interleaved(
+                    benchmark,
+                    &dirs,
+                    *schedule,
+                    adaptive.as_ref(),
+                    cached,
+                    &mut order,
+                )
+            })
+            .collect()
+    })?;
+
+    for (side, lookup) in [Side::Base, Side::Head].into_iter().zip(&lookups) {
+        let summaries: Vec<Summary> = results
+            .iter()
+            .map(|pair| [&pair.0, &pair.1][side.index()].clone())
+            .collect();
+        cache_store(execution_context, lookup.as_ref(), &summaries)?;
+    }
+    let [base_cached, head_cached] = cached.map(|summaries| summaries.is_some());
+    Ok((results, base_cached, head_cached))
+}
+
+/// Measure a benchmark on both sides, a run of each side at a time in the order
+/// given by the schedule, until the configured or adaptive run count is reached.
+/// Sides with cached results are not run, their cached runs are used instead.
+fn measure_interleaved(
+    benchmark: &Benchmark,
+    dirs: &BuildDirs,
+    schedule: Schedule,
+    adaptive: Option<&Adaptive>,
+    cached: [Option<&Summary>; 2],
+    order: &mut usize,
+) -> Result<(Summary, Summary)> {
+    let commands = [Side::Base, Side::Head].map(|side| dirs.relocate(&benchmark.command, side));
+    let hooks = [Side::Base, Side::Head]
+        .map(|side| benchmark.hooks.relocate(&dirs.workdir, &dirs.dir(side)));
+    let mut runs = cached.map(|summary| {
+        summary
+            .map(|summary| summary.runs.clone())
+            .unwrap_or_default()
+    });
+    let started = Instant::now();
+    let is_done = |runs: &[Vec<Results>; 2]| {
+        let wall_times = |runs: &[Results]| -> Vec<f64> {
+            runs.iter().map(|run| run.wall_time.as_secs_f64()).collect()
+        };
+        // Cached sides have all their runs already.
+        let fewest = runs
+            .iter()
+            .zip(cached)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(runs, _)| runs.len())
+            .min()
+            .unwrap_or_default();
+        adaptive.map_or(fewest >= benchmark.runs, |adaptive| {
+            fewest >= adaptive.max_runs
+                || adaptive.is_done(
+                    benchmark.runs,
+                    &wall_times(&runs[0]),
+                    &wall_times(&runs[1]),
+                    started.elapsed(),
+                )
+        })
+    };
+    while !is_done(&runs) {
+        for side in schedule.run_order(1) {
+            let index = side.index();
+            if cached[index].is_some() {
+                continue;
+            }
+            let mut results = measure_run(&commands[index], &hooks[index], &benchmark.metrics)?;
+            results.order = *order;
+            *order += 1;
+            runs[index].push(results);
+        }
+    }
+    let [base, head] = runs;
+    Ok((Summary::from_runs(base), Summary::from_runs(head)))
+}
@@ -0,0 +1,90 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Order in which the runs of base and head are executed.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    /// All runs of base, then all runs of head, in the repository itself.
+    #[default]
+    Sequential,
+    /// Alternate base and head runs (ABAB...), using separate build directories.
+    Alternate,
+    /// Run each pair of base and head runs in random order (AB or BA),
+    /// using separate build directories.
+    Random,
+}
+
+/// Side of a comparison.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// The base reference.
+    Base,
+    /// The head reference.
+    Head,
+}
+
+impl Side {
+    /// Lowercase name of the side.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Base => "base",
+            Self::Head => "head",
+        }
+    }
+
+    /// Index of the side in pairs of base and head.
+    #[must_use]
+    pub const fn index(self) -> usize {
+        match self {
+            Self::Base => 0,
+            Self::Head => 1,
+        }
+    }
+}
+
+impl Schedule {
+    /// Whether base and head runs are interleaved.
+    #[must_use]
+    pub const fn is_interleaved(self) -> bool {
+        !matches!(self, Self::Sequential)
+    }
+
+    /// The order of `runs` runs on each side.
+    #[must_use]
+    pub fn run_order(self, runs: usize) -> Vec<Side> {
+        match self {
+            Self::Sequential => [Side::Base, Side::Head]
+                .into_iter()
+                .flat_map(|side| std::iter::repeat_n(side, runs))
+                .collect(),
+            Self::Alternate => (0..runs).flat_map(|_| [Side::Base, Side::Head]).collect(),
+            Self::Random => (0..runs)
+                .flat_map(|_| {
+                    if fastrand::bool() {
+                        [Side::Base, Side::Head]
+                    } else {
+                        [Side::Head, Side::Base]
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Schedule, Side};
+
+    #[test]
+    fn run_order() {
+        use Side::{Base, Head};
+        assert_eq!(Schedule::Sequential.run_order(2), [Base, Base, Head, Head]);
+        assert_eq!(Schedule::Alternate.run_order(2), [Base, Head, Base, Head]);
+        let random = Schedule::Random.run_order(10);
+        assert_eq!(random.len(), 20);
+        // Every pair contains one run of each side.
+        assert!(random.chunks(2).all(|pair| pair[0] != pair[1]));
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Signals that interrupt measuring.
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/// Last interrupting signal received, or zero.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Process group ID of the running child command, or zero.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Record the signal and kill the running command.
+extern "C" fn handle(signal: libc::c_int) {
+    RECEIVED.store(signal, Ordering::SeqCst);
+    kill_child();
+}
+
+/// Kill the process group of the running command, if any.
+fn kill_child() {
+    let group = CHILD.load(Ordering::SeqCst);
+    if group != 0 {
+        // SAFETY: `kill` is async-signal-safe.
+        unsafe {
+            libc::kill(-group, libc::SIGKILL);
+        }
+    }
+}
+
+/// Handle SIGINT and SIGTERM by killing the running command instead of exiting,
+/// so that the failing measurement unwinds and the repository is restored.
+///
+/// A second signal of the same kind terminates as usual, e.g. if restoring hangs.
+///
+/// # Errors
+///
+/// Returns the OS error if a handler cannot be installed.
+pub fn install() -> io::Result<()> {
+    for signal in SIGNALS {
+        // SAFETY: `sigaction` is plain old data, and the handler only uses
+        // atomics and async-signal-safe functions.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART | libc::SA_RESETHAND;
+            libc::sigemptyset(&raw mut action.sa_mask);
+            if libc::sigaction(signal, &raw const action, std::ptr::null_mut()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Name of the interrupting signal received, if any.
+#[must_use]
+pub fn received() -> Option<&'static str> {
+    match RECEIVED.load(Ordering::SeqCst) {
+        libc::SIGINT => Some("SIGINT"),
+        libc::SIGTERM => Some("SIGTERM"),
+        _ => None,
+    }
+}
+
+/// Fail if an interrupting signal was received.
+///
+/// # Errors
+///
+/// Returns an error naming the signal.
+pub fn check() -> Result<()> {
+    received().map_or(Ok(()), |signal| Err(anyhow!("Interrupted by {signal}")))
+}
+
+/// Start a command in a process group of its own,
+/// which is killed together with all its descendants on interruption.
+///
+/// # Errors
+///
+/// Returns an error if interrupted already, or if the command cannot be started.
+pub fn spawn(command: &mut Command) -> Result<Tracked> {
+    check()?;
+    let child = command.process_group(0).spawn()?;
+    #[allow(clippy::cast_possible_wrap)]
+    CHILD.store(child.id() as i32, Ordering::SeqCst);
+    // The signal may have arrived before the child was known.
+    if check().is_err() {
+        kill_child();
+    }
+    Ok(Tracked(child))
+}
+
+/// Run a command to completion like [`Command::status`], killing it on interruption.
+///
+/// # Errors
+///
+/// Returns an error if the command cannot be started or waited for, or was interrupted.
+pub fn status(command: &mut Command) -> Result<ExitStatus> {
+    let mut child = spawn(command)?;
+    let status = child.wait()?;
+    check()?;
+    Ok(status)
+}
+
+/// A child process which is killed on interruption, until dropped.
+pub struct Tracked(Child);
+
+impl std::ops::Deref for Tracked {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Tracked {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.0
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        CHILD.store(0, Ordering::SeqCst);
+    }
+}
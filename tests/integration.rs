@@ -1,11 +1,16 @@
 //! Integration tests
 use anyhow::{Context, Result};
+use clap::Parser;
 use git_perfdiff::{
     cli,
-    config::{Config, ExecutionContext},
-    measurement::{self, Results},
+    config::{load_config_file, Config, ExecutionContext},
+    git::{self, OriginalHead},
+    measurement::{self, Comparison, Results},
+    runner,
 };
 use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
 mod utils;
 use utils::{git_add, git_commit, git_init, TestContext};
@@ -64,36 +69,505 @@ fn test_integration() -> Result<()> {
     git_add(&ctx.repo, &[build_script_name])?;
     let head_sha = git_commit(&ctx.repo, "Changed script")?;
 
-    let args: Config = cli::Args {
-        command: Some("/bin/sh".to_string()),
-        arg: Some(Vec::from([script_name.to_owned()])),
-        build_command: Some("/bin/sh".to_string()),
-        build_arg: Some(Vec::from([build_script_name.to_str().unwrap().to_string()])),
-        working_dir: None,
-        show_output: Some(false),
-        path: Some(ctx.path.clone()),
-        base: Some(base_sha.to_string()),
-        head: Some(head_sha.to_string()),
-    }
+    let args: Config = cli::Args::try_parse_from([
+        "git-perfdiff",
+        "--command",
+        "/bin/sh",
+        "--arg",
+        script_name,
+        "--build-command",
+        "/bin/sh",
+        "--build-arg",
+        build_script_name.to_str().unwrap(),
+        "--path",
+        ctx.path.to_str().unwrap(),
+        &base_sha.to_string(),
+        &head_sha.to_string(),
+    ])?
     .into();
 
     let execution_context = ExecutionContext::from_config(args.extend_with(Config::default()))
         .expect("Configuration failed to validate");
     let build_command = &execution_context.build_command.unwrap();
-    let command_config = &execution_context.command;
+    let command_config = &execution_context.benchmarks[0].command;
     let diff_targets = &execution_context.git_targets;
 
     ctx.checkout(diff_targets.base_ref.to_string())?;
 
-    build_command.to_command().status()?;
-    let Results { wall_time, .. } = measurement::record_runtime(command_config)?;
+    build_command.to_command()?.status()?;
+    let Results { wall_time, .. } = measurement::record_runtime(command_config, &[])?;
     assert!(wall_time.as_secs_f64() < PERFORMANCE_EPSILON);
 
     assert!(gitignore.exists());
     ctx.checkout(diff_targets.head_ref.to_string())?;
 
-    build_command.to_command().status()?;
-    let Results { wall_time, .. } = measurement::record_runtime(command_config)?;
+    build_command.to_command()?.status()?;
+    let Results { wall_time, .. } = measurement::record_runtime(command_config, &[])?;
     assert!((wall_time.as_secs_f64() - sleep_duration).abs() < PERFORMANCE_EPSILON);
     Ok(())
 }
+
+#[test]
+fn test_working_tree() -> Result<()> {
+    let test_repo_path = Path::new("/tmp/git-perfdiff/working-tree");
+    if test_repo_path.exists() {
+        std::fs::remove_dir_all(test_repo_path)?;
+    }
+
+    let TestContext(ctx) = &git_init(test_repo_path)?;
+    let script_name = Path::new("script.sh");
+    let script_path = ctx.path.join(script_name);
+    std::fs::write(&script_path, "echo committed > out.txt")?;
+    git_add(&ctx.repo, &[script_name])?;
+    git_commit(&ctx.repo, "Added script")?;
+    // Uncommitted changes are measured as they are.
+    std::fs::write(&script_path, "echo uncommitted > out.txt")?;
+
+    let args: Config = cli::Args::try_parse_from([
+        "git-perfdiff",
+        "--shell",
+        "sh script.sh",
+        "--working-dir",
+        ctx.path.to_str().unwrap(),
+        "--path",
+        ctx.path.to_str().unwrap(),
+        "run",
+    ])?
+    .into();
+    let mut config = args.extend_with(Config::default());
+    // There is no main branch to compare against, as in the `run` subcommand.
+    config.base_git_ref.clone_from(&config.head_git_ref);
+    let execution_context =
+        ExecutionContext::from_config(config).expect("Configuration failed to validate");
+    let results = runner::measure_working_tree(&execution_context)?;
+
+    assert_eq!(results.benchmarks.len(), 1);
+    assert_eq!(
+        std::fs::read_to_string(ctx.path.join("out.txt"))?,
+        "uncommitted\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&script_path)?,
+        "echo uncommitted > out.txt"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_snapshot() -> Result<()> {
+    let test_repo_path = Path::new("/tmp/git-perfdiff/snapshot");
+    if test_repo_path.exists() {
+        std::fs::remove_dir_all(test_repo_path)?;
+    }
+
+    let TestContext(ctx) = &git_init(test_repo_path)?;
+    let tracked = Path::new("tracked");
+    std::fs::write(ctx.path.join(tracked), "committed")?;
+    git_add(&ctx.repo, &[tracked])?;
+    let head_sha = git_commit(&ctx.repo, "Added tracked file")?;
+
+    std::fs::write(ctx.path.join(tracked), "staged")?;
+    git_add(&ctx.repo, &[tracked])?;
+    std::fs::write(ctx.path.join(tracked), "unstaged")?;
+    std::fs::write(ctx.path.join("untracked"), "untracked")?;
+
+    let snapshot = ctx.snapshot()?;
+    assert_eq!(
+        std::fs::read_to_string(ctx.path.join(tracked))?,
+        "committed"
+    );
+    assert!(!ctx.path.join("untracked").exists());
+    ctx.checkout(snapshot.commit.to_string())?;
+    assert_eq!(std::fs::read_to_string(ctx.path.join(tracked))?, "unstaged");
+    ctx.checkout(head_sha.to_string())?;
+
+    ctx.restore(&snapshot)?;
+    assert_eq!(std::fs::read_to_string(ctx.path.join(tracked))?, "unstaged");
+    assert_eq!(
+        std::fs::read_to_string(ctx.path.join("untracked"))?,
+        "untracked"
+    );
+    let staged = ctx.repo.index()?.get_path(tracked, 0).unwrap();
+    assert_eq!(
+        ctx.repo.find_blob(staged.id)?.content(),
+        b"staged".as_slice()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_interrupt() -> Result<()> {
+    let test_repo_path = Path::new("/tmp/git-perfdiff/interrupt");
+    if test_repo_path.exists() {
+        std::fs::remove_dir_all(test_repo_path)?;
+    }
+
+    let TestContext(ctx) = &git_init(test_repo_path)?;
+    let file = Path::new("file");
+    std::fs::write(ctx.path.join(file), "base")?;
+    git_add(&ctx.repo, &[file])?;
+    let base_sha = git_commit(&ctx.repo, "Base")?;
+    std::fs::write(ctx.path.join(file), "head")?;
+    git_add(&ctx.repo, &[file])?;
+    let head_sha = git_commit(&ctx.repo, "Head")?;
+    let branch = ctx.repo.head()?.name().unwrap().to_string();
+    // The shell forks a grandchild, which must be killed with it.
+    let grandchild = ctx.repo.path().join("grandchild");
+    let shell = format!("sleep 10 & echo $! > {}; wait", grandchild.display());
+
+    let started = Instant::now();
+    let child = Command::new(env!("CARGO_BIN_EXE_git-perfdiff"))
+        .args(["--shell", &shell, "--path", ctx.path.to_str().unwrap()])
+        .args([base_sha.to_string(), head_sha.to_string()])
+        .current_dir(&ctx.path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Wait until base is checked out and being measured.
+    while !ctx.repo.head_detached()? || !grandchild.exists() {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    // SAFETY: Sending a signal is a plain system call.
+    unsafe {
+        libc::kill(i32::try_from(child.id())?, libc::SIGINT);
+    }
+    let output = child.wait_with_output()?;
+
+    assert!(!output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(10));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("Interrupted by SIGINT, restored HEAD to {branch}")));
+    assert!(!ctx.repo.head_detached()?);
+    assert_eq!(ctx.repo.head()?.name(), Some(branch.as_str()));
+    assert_eq!(std::fs::read_to_string(ctx.path.join(file))?, "head");
+    // Killed processes may linger as zombies until reaped, but no longer run.
+    let pid = std::fs::read_to_string(&grandchild)?;
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+    assert!(stat.is_empty() || stat.contains(") Z "), "{stat}");
+    Ok(())
+}
+
+/// Create a repository with a base and a head commit changing `file`.
+fn init_two_commits(path: &Path) -> Result<(TestContext, String, String)> {
+    if path.exists() {
+        std::fs::remove_dir_all(path)?;
+    }
+    let test_ctx = git_init(path)?;
+    let TestContext(ctx) = &test_ctx;
+    let file = Path::new("file");
+    std::fs::write(ctx.path.join(file), "base")?;
+    git_add(&ctx.repo, &[file])?;
+    let base_sha = git_commit(&ctx.repo, "Base")?;
+    std::fs::write(ctx.path.join(file), "head")?;
+    git_add(&ctx.repo, &[file])?;
+    let head_sha = git_commit(&ctx.repo, "Head")?;
+    Ok((test_ctx, base_sha.to_string(), head_sha.to_string()))
+}
+
+/// Compare base and head in a repository with a trivial command and further arguments.
+fn compare_in(ctx: &git::Context, base: &str, head: &str, args: &[&str]) -> Result<Comparison> {
+    let args: Config = cli::Args::try_parse_from(
+        [
+            "git-perfdiff",
+            "--shell",
+            "true",
+            "--path",
+            ctx.path.to_str().unwrap(),
+            base,
+            head,
+        ]
+        .iter()
+        .chain(args),
+    )?
+    .into();
+    let execution_context = ExecutionContext::from_config(args.extend_with(Config::default()))?;
+    runner::compare(&execution_context)
+}
+
+#[test]
+fn test_commit_hooks() -> Result<()> {
+    let (test_ctx, base, head) = init_two_commits(Path::new("/tmp/git-perfdiff/hooks"))?;
+    let TestContext(ctx) = &test_ctx;
+    // Kept out of the working tree, but removed with the repository.
+    let log = &ctx.repo.path().join("hooks.log");
+    let config_path = &ctx.repo.path().join("perfdiff.toml");
+    let compare = |schedule: &str, setup: &str| -> Result<String> {
+        std::fs::write(
+            config_path,
+            format!(
+                r#"
+                    runs = 2
+                    setup = "{setup} && echo setup >> {log}"
+                    teardown = "echo teardown >> {log}"
+                    [[benchmark]]
+                    name = "first"
+                    shell = "true"
+                    [[benchmark]]
+                    name = "second"
+                    shell = "true"
+                "#,
+                log = log.display()
+            ),
+        )?;
+        let _ = std::fs::remove_file(log);
+        let args: Config = cli::Args::try_parse_from([
+            "git-perfdiff",
+            "--path",
+            ctx.path.to_str().unwrap(),
+            "--schedule",
+            schedule,
+            &base,
+            &head,
+        ])?
+        .into();
+        let config = args
+            .extend_with(load_config_file(config_path))
+            .extend_with(Config::default());
+        let measured = runner::compare(&ExecutionContext::from_config(config)?);
+        let log = std::fs::read_to_string(log).unwrap_or_default();
+        Ok(format!(
+            "{}{log}",
+            if measured.is_ok() { "" } else { "failed\n" }
+        ))
+    };
+
+    // Once per commit, not per benchmark.
+    assert_eq!(
+        compare("sequential", "true")?,
+        "setup\nteardown\nsetup\nteardown\n"
+    );
+    assert_eq!(
+        compare("alternate", "true")?,
+        "setup\nsetup\nteardown\nteardown\n"
+    );
+    // Base is torn down even though the setup of head fails.
+    assert_eq!(
+        compare("alternate", "grep -q base file")?,
+        "failed\nsetup\nteardown\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_restore_branch() -> Result<()> {
+    let (test_ctx, base, head) = init_two_commits(Path::new("/tmp/git-perfdiff/branch"))?;
+    let TestContext(ctx) = &test_ctx;
+    let original = ctx.original_head()?;
+    assert!(matches!(original, OriginalHead::Branch(_)));
+
+    compare_in(ctx, &base, &head, &[])?;
+    assert_eq!(ctx.original_head()?, original);
+    assert_eq!(std::fs::read_to_string(ctx.path.join("file"))?, "head");
+    Ok(())
+}
+
+#[test]
+fn test_failing_build() -> Result<()> {
+    let (test_ctx, base, head) = init_two_commits(Path::new("/tmp/git-perfdiff/build"))?;
+    let TestContext(ctx) = &test_ctx;
+    let original = ctx.original_head()?;
+
+    let Err(err) = compare_in(ctx, &base, &head, &["--build-shell", "exit 3"]) else {
+        panic!("A failing build command should fail measuring");
+    };
+    assert!(err.to_string().contains("Build command failed"));
+    assert_eq!(ctx.original_head()?, original);
+    Ok(())
+}
+
+#[test]
+fn test_restore_detached() -> Result<()> {
+    let (test_ctx, base, head) = init_two_commits(Path::new("/tmp/git-perfdiff/detached"))?;
+    let TestContext(ctx) = &test_ctx;
+    ctx.checkout(&base)?;
+    assert_eq!(
+        ctx.original_head()?,
+        OriginalHead::Detached(git2::Oid::from_str(&base)?)
+    );
+
+    compare_in(ctx, &base, &head, &[])?;
+    assert_eq!(
+        ctx.original_head()?,
+        OriginalHead::Detached(git2::Oid::from_str(&base)?)
+    );
+    assert_eq!(std::fs::read_to_string(ctx.path.join("file"))?, "base");
+    Ok(())
+}
+
+#[test]
+fn test_restore_unborn() -> Result<()> {
+    let (test_ctx, base, head) = init_two_commits(Path::new("/tmp/git-perfdiff/unborn"))?;
+    let TestContext(ctx) = &test_ctx;
+    // Switch to a new branch without any commits or files.
+    let orphan = OriginalHead::Branch("refs/heads/orphan".to_string());
+    ctx.restore_head(&orphan)?;
+    assert_eq!(ctx.original_head()?, orphan);
+    assert!(!ctx.path.join("file").exists());
+
+    compare_in(ctx, &base, &head, &[])?;
+    assert_eq!(ctx.original_head()?, orphan);
+    assert!(ctx.repo.head().is_err());
+    assert!(!ctx.path.join("file").exists());
+    Ok(())
+}
+
+#[test]
+fn test_lock() -> Result<()> {
+    let (test_ctx, base, _) = init_two_commits(Path::new("/tmp/git-perfdiff/lock"))?;
+    let TestContext(ctx) = &test_ctx;
+    let lock_path = ctx.repo.path().join("perfdiff.lock");
+
+    let mut holder = Command::new("flock")
+        .args(["--close", lock_path.to_str().unwrap(), "sleep", "10"])
+        .spawn()?;
+    std::fs::write(&lock_path, format!("{}\nsleep 10\n", holder.id()))?;
+    // Wait for the holder to lock the file.
+    while Command::new("flock")
+        .args(["--nonblock", lock_path.to_str().unwrap(), "true"])
+        .status()?
+        .success()
+    {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let error = ctx.checkout(&base).unwrap_err().to_string();
+    assert!(error.contains(&format!("locked by process {} (`sleep 10`)", holder.id())));
+
+    // The lock file of a process that no longer runs is not locked.
+    holder.kill()?;
+    holder.wait()?;
+    ctx.checkout(&base)?;
+    let contents = std::fs::read_to_string(&lock_path)?;
+    assert!(contents.starts_with(&format!("{}\n", std::process::id())));
+    Ok(())
+}
+
+/// Run a git command in a directory, failing if it does not succeed.
+fn git(dir: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new("git")
+        .args([
+            "-c",
+            "protocol.file.allow=always",
+            "-C",
+            dir.to_str().unwrap(),
+        ])
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    assert!(status.success(), "git {args:?} failed");
+    Ok(())
+}
+
+#[test]
+fn test_submodules() -> Result<()> {
+    let (lib_ctx, lib_v1, lib_v2) = init_two_commits(Path::new("/tmp/git-perfdiff/sub-lib"))?;
+    let TestContext(lib) = &lib_ctx;
+    let (test_ctx, _, _) = init_two_commits(Path::new("/tmp/git-perfdiff/sub-super"))?;
+    let TestContext(ctx) = &test_ctx;
+
+    git(
+        &ctx.path,
+        &["submodule", "add", lib.path.to_str().unwrap(), "lib"],
+    )?;
+    let lib_path = ctx.path.join("lib");
+    git(&lib_path, &["checkout", "-q", &lib_v1])?;
+    git(&ctx.path, &["commit", "-qam", "Add lib at base"])?;
+    let base = ctx.repo.head()?.target().unwrap().to_string();
+    git(&lib_path, &["checkout", "-q", &lib_v2])?;
+    git(&ctx.path, &["commit", "-qam", "Update lib"])?;
+    // The submodule is on a branch at the recorded commit.
+    git(&lib_path, &["checkout", "-q", "-B", "work"])?;
+
+    let original = ctx.original_head()?;
+    ctx.checkout(&base)?;
+    assert_eq!(std::fs::read_to_string(lib_path.join("file"))?, "base");
+
+    ctx.restore_head(&original)?;
+    assert_eq!(std::fs::read_to_string(lib_path.join("file"))?, "head");
+    let lib_ctx = git::Context::try_from(lib_path)?;
+    assert_eq!(
+        lib_ctx.original_head()?,
+        OriginalHead::Branch("refs/heads/work".to_string())
+    );
+
+    let export = Path::new("/tmp/git-perfdiff/sub-export");
+    ctx.export(&base, export)?;
+    assert_eq!(std::fs::read_to_string(export.join("lib/file"))?, "base");
+    std::fs::remove_dir_all(export)?;
+    Ok(())
+}
+
+#[test]
+fn test_paths() -> Result<()> {
+    let (test_ctx, base, head) = init_two_commits(Path::new("/tmp/git-perfdiff/paths"))?;
+    let TestContext(ctx) = &test_ctx;
+
+    let comparison = compare_in(ctx, &base, &head, &["--paths", "src,docs"])?;
+    assert!(comparison.head_reused);
+    assert_eq!(comparison.benchmarks[0].head.runs.len(), 1);
+    let comparison = compare_in(ctx, &base, &head, &["--paths", "src,fil*"])?;
+    assert!(!comparison.head_reused);
+    Ok(())
+}
+
+#[test]
+fn test_cache() -> Result<()> {
+    let (test_ctx, base, head) = init_two_commits(Path::new("/tmp/git-perfdiff/cache"))?;
+    let TestContext(ctx) = &test_ctx;
+
+    let comparison = compare_in(ctx, &base, &head, &["--cache", "true"])?;
+    assert!(!comparison.base_cached && !comparison.head_cached);
+
+    // A commit with a new OID, but the same tree as head.
+    let head_commit = ctx.repo.find_commit(git2::Oid::from_str(&head)?)?;
+    let signature = ctx.repo.signature()?;
+    let rebased = ctx.repo.commit(
+        None,
+        &signature,
+        &signature,
+        "Rebased",
+        &head_commit.tree()?,
+        &[&head_commit],
+    )?;
+    let comparison = compare_in(ctx, &base, &rebased.to_string(), &["--cache", "true"])?;
+    assert!(comparison.base_cached && comparison.head_cached);
+    assert_eq!(comparison.head_ref, rebased.to_string());
+
+    // Different settings are measured anew.
+    let comparison = compare_in(ctx, &base, &head, &["--cache", "true", "--runs", "2"])?;
+    assert!(!comparison.base_cached && !comparison.head_cached);
+
+    // Interleaved schedules measure only the sides without cached results.
+    let adaptive = [
+        "--cache",
+        "true",
+        "--schedule",
+        "alternate",
+        "--target-ci-width",
+        "1000",
+        "--runs",
+        "3",
+    ];
+    let comparison = compare_in(ctx, &base, &head, &adaptive)?;
+    assert!(!comparison.base_cached && !comparison.head_cached);
+    let blob = ctx.repo.blob(b"other")?;
+    let mut builder = ctx.repo.treebuilder(None)?;
+    builder.insert("file", blob, 0o100_644)?;
+    let tree = ctx.repo.find_tree(builder.write()?)?;
+    let other = ctx.repo.commit(
+        None,
+        &signature,
+        &signature,
+        "Other",
+        &tree,
+        &[&head_commit],
+    )?;
+    let comparison = compare_in(ctx, &base, &other.to_string(), &adaptive)?;
+    assert!(comparison.base_cached && !comparison.head_cached);
+    assert!(comparison.benchmarks[0].head.runs.len() >= 3);
+    let comparison = compare_in(ctx, &base, &rebased.to_string(), &adaptive)?;
+    assert!(comparison.base_cached && comparison.head_cached);
+    Ok(())
+}
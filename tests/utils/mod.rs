@@ -10,7 +10,7 @@ impl Drop for TestContext {
         let Self(ctx) = self;
         let repo_path = &ctx.path;
         std::fs::remove_dir_all(repo_path)
-            .unwrap_or_else(|_| panic!("Git repo at path {repo_path:#?} not deleted"));
+            .unwrap_or_else(|_| panic!("Git repo at path {} not deleted", repo_path.display()));
     }
 }
 
@@ -31,20 +31,18 @@ pub fn initial_commit(repo: &git2::Repository) -> Result<Oid> {
 pub fn git_init(path: &Path) -> Result<TestContext> {
     // Check if directory already exists
     if path.try_exists().unwrap_or_default() {
-        return Err(anyhow!(format!("Directory {path:#?} already exists!")));
+        return Err(anyhow!("Directory {} already exists!", path.display()));
     }
 
     // Attempt to create directory.
-    std::fs::create_dir_all(path).with_context(|| "Failed to create directory {path:#?}")?;
+    std::fs::create_dir_all(path)
+        .with_context(|| format!("Failed to create directory {}", path.display()))?;
 
-    let repo =
-        git2::Repository::init(path).with_context(|| "Failed to create repository at {path:#?}")?;
+    let repo = git2::Repository::init(path)
+        .with_context(|| format!("Failed to create repository at {}", path.display()))?;
     initial_commit(&repo)?;
 
-    Ok(TestContext(git::Context {
-        repo,
-        path: path.to_path_buf(),
-    }))
+    Ok(TestContext(git::Context::try_from(path.to_path_buf())?))
 }
 
 pub fn git_add(repo: &Repository, paths: &[&Path]) -> Result<()> {